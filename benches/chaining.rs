@@ -0,0 +1,103 @@
+use bio::io::fasta;
+use chrom_mini_graph::chain;
+use chrom_mini_graph::constants;
+use chrom_mini_graph::data_structs::{SeedMethod, SeedParams};
+use chrom_mini_graph::graph_utils;
+use chrom_mini_graph::seeding_methods_bit;
+use debruijn::dna_string::DnaString;
+use fxhash::{FxHashMap, FxHashSet};
+use std::fs;
+use std::time::Instant;
+
+//Times genome-to-graph chaining of the test_refs genomes against the
+//first one, as done by generate. Run with
+//cargo bench --bench chaining
+fn main() {
+    let mut paths: Vec<_> = fs::read_dir("test_refs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    let mut genomes = vec![];
+    for path in paths.iter() {
+        let reader = fasta::Reader::from_file(path).unwrap();
+        for record in reader.records() {
+            let rec = record.unwrap();
+            genomes.push(DnaString::from_acgt_bytes(rec.seq()));
+        }
+    }
+
+    let params = constants::default_params();
+    let seed_params = SeedParams {
+        method: SeedMethod::Minimizer,
+        k: constants::K,
+        w: params.w,
+        s: 0,
+        t: 0,
+        strobe_w_min: 0,
+        strobe_w_max: 0,
+        hpc: false,
+        soft_mask: false,
+        seed_hash: constants::SEED_HASH.to_string(),
+    };
+    let dont_use_kmers = FxHashSet::default();
+    let frequent_kmers = FxHashMap::default();
+    let (mut seeds_ref, _) = seeding_methods_bit::seed_sequence(
+        &genomes[0],
+        &seed_params,
+        30,
+        &dont_use_kmers,
+        &frequent_kmers,
+        &vec![],
+        true,
+    );
+    graph_utils::top_sort(&mut seeds_ref);
+    let ref_hash_map = chain::get_kmer_dict_mut(&mut seeds_ref);
+
+    let mut results = vec![];
+    let mut total_secs = 0.;
+    for genome in genomes[1..].iter() {
+        let (seeds_q, _) = seeding_methods_bit::seed_sequence(
+            genome,
+            &seed_params,
+            1,
+            &dont_use_kmers,
+            &frequent_kmers,
+            &vec![],
+            false,
+        );
+        let q_hash_map = chain::get_kmer_dict(&seeds_q);
+        let now = Instant::now();
+        let chains = chain::chain_seeds(
+            &seeds_ref,
+            &seeds_q,
+            &ref_hash_map,
+            &q_hash_map,
+            &params,
+            true,
+            false,
+            &dont_use_kmers,
+            false,
+            seed_params.method,
+        );
+        let secs = now.elapsed().as_secs_f64();
+        total_secs += secs;
+        let best = chains
+            .iter()
+            .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
+            .unwrap();
+        results.push((seeds_q.len(), best.0.len(), best.1, secs));
+    }
+
+    for (i, (num_seeds, chain_len, score, secs)) in results.iter().enumerate() {
+        println!(
+            "genome {}: {} seeds, best chain {} anchors, score {}, {:.3} seconds",
+            i + 1,
+            num_seeds,
+            chain_len,
+            score,
+            secs
+        );
+    }
+    println!("Total chaining time {:.3} seconds", total_secs);
+}
@@ -0,0 +1,87 @@
+use bio::io::fasta;
+use chrom_mini_graph::seeding_methods_bit;
+use debruijn::dna_string::DnaString;
+use fxhash::{FxHashMap, FxHashSet};
+use std::fs;
+use std::time::Instant;
+
+//Times minimizer seeding over the test_refs genomes with rolling k-mers and the
+//monotone deque, and with the sliced k-mers and ring buffer they replaced. Run
+//with cargo bench --bench seeding
+fn main() {
+    let mut paths: Vec<_> = fs::read_dir("test_refs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    let mut genomes = vec![];
+    let mut total_len = 0;
+    for path in paths.iter() {
+        let reader = fasta::Reader::from_file(path).unwrap();
+        for record in reader.records() {
+            let rec = record.unwrap();
+            total_len += rec.seq().len();
+            genomes.push(DnaString::from_acgt_bytes(rec.seq()));
+        }
+    }
+
+    let dont_use_kmers = FxHashSet::default();
+    let frequent_kmers = FxHashMap::default();
+    let k = 16;
+    let iters = 5;
+    for &w in [10, 16, 50].iter() {
+        //Each iteration times both versions, one after the other, so that
+        //neither pays alone for warming up the caches and allocator
+        let mut times = vec![0.; 2];
+        let mut num_seeds = vec![0; 2];
+        for iter in 0..iters + 1 {
+            for (version, deque) in [true, false].iter().enumerate() {
+                let now = Instant::now();
+                for genome in genomes.iter() {
+                    let seeds;
+                    if *deque {
+                        seeds = seeding_methods_bit::minimizer_seeds(
+                            genome,
+                            w,
+                            k,
+                            30,
+                            &dont_use_kmers,
+                            &frequent_kmers,
+                            true,
+                        );
+                    } else {
+                        seeds = seeding_methods_bit::sliced_minimizer_seeds(
+                            genome,
+                            w,
+                            k,
+                            30,
+                            &dont_use_kmers,
+                            &frequent_kmers,
+                            true,
+                        );
+                    }
+                    if iter == 0 {
+                        num_seeds[version] += seeds.0.len();
+                    }
+                }
+                //The first iteration only warms up
+                if iter > 0 {
+                    times[version] += now.elapsed().as_secs_f64() / iters as f64;
+                }
+            }
+        }
+        assert_eq!(num_seeds[0], num_seeds[1]);
+        println!(
+            "w = {}, k = {}: {} seeds over {} bases. Rolling and deque {:.3} seconds ({:.1} ns/base), sliced k-mers and ring buffer {:.3} seconds ({:.1} ns/base), speedup {:.2}x",
+            w,
+            k,
+            num_seeds[0],
+            total_len,
+            times[0],
+            times[0] * 1e9 / total_len as f64,
+            times[1],
+            times[1] * 1e9 / total_len as f64,
+            times[1] / times[0]
+        );
+    }
+}
@@ -1,17 +1,20 @@
 use crate::align;
+use crate::aligner::Aligner;
+use crate::constants;
 use crate::data_structs::{Anchors, Color};
-use crate::data_structs::{BamInfo, KmerNode};
+use crate::data_structs::{BamInfo, KmerNode, Mapping, PafInfo, Params};
+use crate::graph_utils;
+use bio::alphabets::dna::revcomp;
 use block_aligner::cigar::*;
-use block_aligner::scan_block::*;
-use block_aligner::scores::*;
 use debruijn::dna_string::DnaString;
 use debruijn::dna_string::*;
 use debruijn::Kmer;
 use debruijn::Mer;
 use fxhash::FxHashSet;
 use rust_htslib::bam::header::{Header, HeaderRecord};
-use rust_htslib::bam::record::{Cigar as hts_Cigar, CigarString, Record};
-use rust_htslib::bam::{Format, HeaderView, Writer};
+use rust_htslib::bam::record::{Aux, Cigar as hts_Cigar, CigarString, Record};
+use rust_htslib::bam::HeaderView;
+use rust_htslib::htslib;
 use std::time::Instant;
 
 #[inline]
@@ -109,6 +112,11 @@ pub fn get_coords(
     let mut num_trav = 0;
     let mut current_anchor = 0;
     let mut visited_nodes = FxHashSet::default();
+    //A walk that dead ends before the last anchor, e.g. at the origin of a
+    //circular genome whose path isn't closed in the graph, restarts at the
+    //next anchor with its own offset. Each walk is (first hit, offset, length).
+    let mut walks = vec![];
+    let mut walk_start = 0;
     loop {
         if parent_node.id == anchors[current_anchor].0 {
             kmer_hit_positions.push((
@@ -240,6 +248,14 @@ pub fn get_coords(
         //                continue;
         //            }
         //        }
+        if !found && current_anchor < anchors.len() {
+            walks.push((walk_start, abs_pos_index, path_dist));
+            walk_start = kmer_hit_positions.len();
+            abs_pos_index = 0;
+            path_dist = 0;
+            parent_node = &ref_nodes[anchors[current_anchor].0 as usize];
+            continue;
+        }
         if !found
             || parent_node.order > last_node.order
             //|| path_dist > 10000000
@@ -257,63 +273,169 @@ pub fn get_coords(
         }
     }
 
+    walks.push((walk_start, abs_pos_index, path_dist));
     //    dbg!(abs_pos_index);
-    if abs_pos_index == 0 {
+    //The interval is that of the first walk with a position in the genome.
+    //A walk that starts more than half the genome behind the hits before it
+    //went on across the origin, so its hits are put past the genome's end as
+    //one walk around the origin would put them.
+    let mut total_interval = (i64::MAX, i64::MAX);
+    let mut hit_positions: Vec<(i64, usize)> = vec![];
+    let genome_len = chrom.len() as i64;
+    for (w, (start, abs_pos_index, path_dist)) in walks.iter().enumerate() {
+        let end;
+        if w + 1 < walks.len() {
+            end = walks[w + 1].0;
+        } else {
+            end = kmer_hit_positions.len();
+        }
+        if *abs_pos_index == 0 {
+            continue;
+        }
+        let abs_pos_index = *abs_pos_index as i64;
+        let path_dist = *path_dist as i64;
+        let mut walk_hits = vec![];
+        if strand {
+            if total_interval.0 == i64::MAX {
+                total_interval = (abs_pos_index, abs_pos_index + path_dist + kmer_length);
+            }
+            for pos in kmer_hit_positions[*start..end].iter() {
+                walk_hits.push((abs_pos_index + pos.0, pos.1));
+            }
+        } else {
+            //        if abs_pos_index < path_dist + kmer_length {
+            //            dbg!(bit, bit_pos, strand);
+            //            dbg!(abs_pos_index, path_dist + kmer_length);
+            //            dbg!(&parent_node, &last_node, &first_node);
+            //            dbg!(&ref_nodes[parent_node.child_nodes[0] as usize]);
+            //
+            //            panic!();
+            //        }
+            if total_interval.0 == i64::MAX {
+                total_interval = (abs_pos_index - path_dist, abs_pos_index + kmer_length);
+            }
+            for pos in kmer_hit_positions[*start..end].iter() {
+                walk_hits.push((abs_pos_index - pos.0, pos.1));
+            }
+        }
+        if let (Some(last), Some(first)) = (hit_positions.last(), walk_hits.first()) {
+            let mut shift = 0;
+            if strand {
+                while last.0 - (first.0 + shift) > genome_len / 2 {
+                    shift += genome_len;
+                }
+            } else {
+                while (first.0 + shift) - last.0 > genome_len / 2 {
+                    shift -= genome_len;
+                }
+            }
+            for hit in walk_hits.iter_mut() {
+                hit.0 += shift;
+            }
+        }
+        hit_positions.extend(walk_hits);
+    }
+    if hit_positions.is_empty() {
         return ((i64::MAX, i64::MAX), vec![]);
     }
-    assert!(abs_pos_index != 0);
-    let total_interval;
-    if strand {
-        total_interval = (
-            abs_pos_index as i64,
-            abs_pos_index + path_dist + kmer_length,
-        );
-        for pos in kmer_hit_positions.iter_mut() {
-            pos.0 += abs_pos_index as i64;
+    return (total_interval, hit_positions);
+}
+
+//Hex MD5 of a sequence, for the M5 tag of @SQ lines
+fn md5_hex(seq: &[u8]) -> String {
+    let mut digest = [0u8; 16];
+    let mut hex = [0u8; 33];
+    unsafe {
+        let context = htslib::hts_md5_init();
+        htslib::hts_md5_update(context, seq.as_ptr() as *const std::os::raw::c_void, seq.len() as _);
+        htslib::hts_md5_final(digest.as_mut_ptr(), context);
+        htslib::hts_md5_destroy(context);
+        htslib::hts_md5_hex(hex.as_mut_ptr() as *mut std::os::raw::c_char, digest.as_ptr());
+    }
+    return String::from_utf8_lossy(&hex[..32]).to_string();
+}
+
+//Tag and value pairs of a read group line like minimap2's -R,
+//'@RG\tID:foo\tSM:bar', with tabs written as \t or literally.
+pub fn read_group_fields(read_group: &str) -> Result<Vec<(String, String)>, String> {
+    let line = read_group.replace("\\t", "\t");
+    let mut fields = vec![];
+    for field in line.split('\t') {
+        if field == "@RG" || field.is_empty() {
+            continue;
         }
-    } else {
-        //        if abs_pos_index < path_dist + kmer_length {
-        //            dbg!(bit, bit_pos, strand);
-        //            dbg!(abs_pos_index, path_dist + kmer_length);
-        //            dbg!(&parent_node, &last_node, &first_node);
-        //            dbg!(&ref_nodes[parent_node.child_nodes[0] as usize]);
-        //
-        //            panic!();
-        //        }
-        total_interval = (
-            abs_pos_index as i64 - path_dist as i64 - kmer_length as i64,
-            abs_pos_index,
-        );
-        for pos in kmer_hit_positions.iter_mut() {
-            pos.0 = abs_pos_index as i64 - pos.0;
+        if field.len() < 3 || field.as_bytes()[2] != b':' {
+            return Err(format!("Bad read group field {} in {}", field, read_group));
         }
+        fields.push((field[..2].to_string(), field[3..].to_string()));
     }
-    return (
-        (total_interval.0 as i64, total_interval.1 as i64),
-        kmer_hit_positions,
-    );
+    if !fields.iter().any(|x| x.0 == "ID") {
+        return Err(format!("Read group {} has no ID", read_group));
+    }
+    return Ok(fields);
+}
+
+//Header values can't hold tabs or newlines, so they are written as \t and
+//\n as minimap2 does
+fn escape_header_value(value: &str) -> String {
+    return value.replace('\t', "\\t").replace('\n', "\\n");
 }
 
-pub fn write_bam_header(
+//@HD gives the sort order, @SQ lines carry M5 checksums, which CRAM needs
+//before the genomes are loaded, @RG is the --rg read group, @PG the command
+//line and each comment becomes a @CO line.
+pub fn bam_header(
     chroms: &Vec<(DnaString, bool)>,
+    chrom_gaps: &Vec<Vec<(usize, usize)>>,
     ref_names: &Vec<String>,
-    bam_name: String,
-) -> (HeaderView, Writer) {
+    sorted: bool,
+    read_group: &Vec<(String, String)>,
+    command_line: &str,
+    comments: &Vec<String>,
+) -> Header {
     let mut header = Header::new();
+    let mut hd_rec = HeaderRecord::new(b"HD");
+    hd_rec.push_tag(b"VN", &"1.6");
+    if sorted {
+        hd_rec.push_tag(b"SO", &"coordinate");
+    } else {
+        hd_rec.push_tag(b"SO", &"unsorted");
+    }
+    header.push_record(&hd_rec);
     for (i, (reference, _strand)) in chroms.iter().enumerate() {
         let mut new_rec = HeaderRecord::new(b"SQ");
         new_rec.push_tag(b"SN", &ref_names[i]);
         new_rec.push_tag(b"LN", &reference.len());
+        let bases = graph_utils::genome_bytes(reference, &chrom_gaps[i], 0, reference.len());
+        new_rec.push_tag(b"M5", &md5_hex(&bases));
         header.push_record(&new_rec);
     }
-    let writer = Writer::from_path(bam_name, &header, Format::Bam).unwrap();
-    return (HeaderView::from_header(&header), writer);
+    if !read_group.is_empty() {
+        let mut rg_rec = HeaderRecord::new(b"RG");
+        for (tag, value) in read_group.iter() {
+            rg_rec.push_tag(tag.as_bytes(), value);
+        }
+        header.push_record(&rg_rec);
+    }
+    let mut pg_rec = HeaderRecord::new(b"PG");
+    pg_rec.push_tag(b"ID", &"chrom_mini_graph");
+    pg_rec.push_tag(b"PN", &"chrom_mini_graph");
+    pg_rec.push_tag(b"VN", &env!("CARGO_PKG_VERSION"));
+    pg_rec.push_tag(b"CL", &escape_header_value(command_line));
+    header.push_record(&pg_rec);
+    for comment in comments.iter() {
+        header.push_comment(comment.as_bytes());
+    }
+    return header;
 }
 
-pub fn get_bam_record(bam_info: BamInfo, headerview: &HeaderView) -> Record {
-    let mut rec = Record::new();
+//Cigar of a record with its soft clips
+fn hts_cigar(bam_info: &BamInfo) -> CigarString {
     let cigar_vec = &bam_info.cigar;
     let mut hts_cigar_vec = vec![];
+    if bam_info.soft_clips.0 > 0 {
+        hts_cigar_vec.push(hts_Cigar::SoftClip(bam_info.soft_clips.0 as u32));
+    }
     for (i,oplen) in cigar_vec.iter().enumerate() {
         let op = oplen.op;
         let len = oplen.len;
@@ -343,12 +465,23 @@ pub fn get_bam_record(bam_info: BamInfo, headerview: &HeaderView) -> Record {
 
         hts_cigar_vec.push(hts_op);
     }
+    if bam_info.soft_clips.1 > 0 {
+        hts_cigar_vec.push(hts_Cigar::SoftClip(bam_info.soft_clips.1 as u32));
+    }
+    return CigarString(hts_cigar_vec);
+}
+
+pub fn get_bam_record(
+    bam_info: BamInfo,
+    headerview: &HeaderView,
+    read_group_id: Option<&str>,
+) -> Record {
+    let mut rec = Record::new();
     let mut scaled_quals = vec![0; bam_info.quals.len()];
     for (i, val) in bam_info.quals.iter().enumerate() {
         scaled_quals[i] = val - 33;
     }
-    let hts_cigar_view = CigarString(hts_cigar_vec);
-    let hts_cigar = Some(hts_cigar_view);
+    let hts_cigar = Some(hts_cigar(&bam_info));
     rec.set(
         bam_info.qname.as_bytes(),
         hts_cigar.as_ref(),
@@ -359,44 +492,391 @@ pub fn get_bam_record(bam_info: BamInfo, headerview: &HeaderView) -> Record {
     if bam_info.strand == false {
         rec.set_reverse();
     }
+    if bam_info.supplementary {
+        rec.set_supplementary();
+    }
+    if bam_info.secondary {
+        rec.set_secondary();
+    }
 
     let tid = headerview.tid(bam_info.ref_name.as_bytes()).unwrap();
     rec.set_tid(tid as i32);
     rec.set_pos(bam_info.map_pos);
     rec.set_mapq(bam_info.mapq);
+
+    rec.push_aux(b"NM", Aux::I32(bam_info.edit_distance as i32)).unwrap();
+    rec.push_aux(b"AS", Aux::I32(bam_info.score)).unwrap();
+    rec.push_aux(b"MD", Aux::String(&bam_info.md)).unwrap();
+    rec.push_aux(b"cs", Aux::String(&bam_info.cs)).unwrap();
+    rec.push_aux(b"de", Aux::Float(bam_info.divergence as f32)).unwrap();
+    let alignment_type;
+    if bam_info.secondary {
+        alignment_type = b'S';
+    } else {
+        alignment_type = b'P';
+    }
+    rec.push_aux(b"tp", Aux::Char(alignment_type)).unwrap();
+    if !bam_info.sa.is_empty() {
+        rec.push_aux(b"SA", Aux::String(&bam_info.sa)).unwrap();
+    }
+    if let Some(id) = read_group_id {
+        rec.push_aux(b"RG", Aux::String(id)).unwrap();
+    }
     return rec;
 }
 
+//SA tag entry of a record: rname,pos,strand,CIGAR,mapQ,NM;
+fn sa_entry(bam_info: &BamInfo) -> String {
+    let strand;
+    if bam_info.strand {
+        strand = '+';
+    } else {
+        strand = '-';
+    }
+    return format!(
+        "{},{},{},{},{},{};",
+        bam_info.ref_name,
+        bam_info.map_pos + 1,
+        strand,
+        hts_cigar(bam_info),
+        bam_info.mapq,
+        bam_info.edit_distance
+    );
+}
+
+//Why a read got no alignment from Mapper::map_read, or None if it has one.
+//no_chain means no chain was found and few_anchors that the chains aligned had
+//fewer than 3 anchors or k-mer hits on the genome.
+pub fn unmapped_reason(mappings: &Vec<Mapping>) -> Option<&'static str> {
+    if mappings.iter().any(|x| !x.bam_info.is_empty()) {
+        return None;
+    }
+    if mappings.iter().any(|x| x.aligned_color.is_some()) {
+        return Some("few_anchors");
+    } else {
+        return Some("no_chain");
+    }
+}
+
+//Unmapped record holding the read's bases uppercased as in mapped records,
+//with the reason in XU.
+pub fn get_unmapped_bam_record(
+    read_id: &str,
+    seq: &[u8],
+    quals: &[u8],
+    reason: &str,
+    read_group_id: Option<&str>,
+) -> Record {
+    let mut rec = Record::new();
+    let scaled_quals: Vec<u8> = quals.iter().map(|x| x - 33).collect();
+    rec.set(read_id.as_bytes(), None, &seq.to_ascii_uppercase(), &scaled_quals);
+    rec.set_unmapped();
+    rec.set_tid(-1);
+    rec.set_pos(-1);
+    rec.set_mtid(-1);
+    rec.set_mpos(-1);
+    rec.push_aux(b"XU", Aux::String(reason)).unwrap();
+    if let Some(id) = read_group_id {
+        rec.push_aux(b"RG", Aux::String(id)).unwrap();
+    }
+    return rec;
+}
+
+//NM, MD, cs (short form) and gap-compressed divergence of an alignment, from
+//its cigar and the aligned parts of the query and reference.
+pub fn alignment_tags(
+    cigar: &[OpLen],
+    query: &[u8],
+    reference: &[u8],
+) -> (usize, String, String, f64) {
+    let mut edit_distance = 0;
+    let mut md = String::new();
+    let mut cs = String::new();
+    let mut matches = 0;
+    let mut mismatches = 0;
+    let mut gap_opens = 0;
+    let mut md_run = 0;
+    let mut cs_run = 0;
+    let mut qi = 0;
+    let mut ri = 0;
+    for oplen in cigar.iter() {
+        if oplen.op == Operation::M {
+            for k in 0..oplen.len {
+                let q = query[qi + k];
+                let r = reference[ri + k];
+                if q == r {
+                    matches += 1;
+                    md_run += 1;
+                    cs_run += 1;
+                } else {
+                    mismatches += 1;
+                    md.push_str(&md_run.to_string());
+                    md.push(r as char);
+                    md_run = 0;
+                    if cs_run > 0 {
+                        cs.push_str(&format!(":{}", cs_run));
+                        cs_run = 0;
+                    }
+                    cs.push('*');
+                    cs.push(r.to_ascii_lowercase() as char);
+                    cs.push(q.to_ascii_lowercase() as char);
+                }
+            }
+            qi += oplen.len;
+            ri += oplen.len;
+        } else if oplen.op == Operation::I {
+            if cs_run > 0 {
+                cs.push_str(&format!(":{}", cs_run));
+                cs_run = 0;
+            }
+            cs.push('+');
+            cs.push_str(&String::from_utf8_lossy(&query[qi..qi + oplen.len]).to_lowercase());
+            edit_distance += oplen.len;
+            gap_opens += 1;
+            qi += oplen.len;
+        } else if oplen.op == Operation::D {
+            md.push_str(&md_run.to_string());
+            md.push('^');
+            md.push_str(&String::from_utf8_lossy(&reference[ri..ri + oplen.len]));
+            md_run = 0;
+            if cs_run > 0 {
+                cs.push_str(&format!(":{}", cs_run));
+                cs_run = 0;
+            }
+            cs.push('-');
+            cs.push_str(&String::from_utf8_lossy(&reference[ri..ri + oplen.len]).to_lowercase());
+            edit_distance += oplen.len;
+            gap_opens += 1;
+            ri += oplen.len;
+        }
+    }
+    md.push_str(&md_run.to_string());
+    if cs_run > 0 {
+        cs.push_str(&format!(":{}", cs_run));
+    }
+    edit_distance += mismatches;
+    let divergence;
+    if matches + mismatches + gap_opens == 0 {
+        divergence = 0.;
+    } else {
+        divergence = (mismatches + gap_opens) as f64 / (matches + mismatches + gap_opens) as f64;
+    }
+    return (edit_distance, md, cs, divergence);
+}
+
+//Append an operation to a cigar, merging it with the last one if they match
+fn push_op(cigar: &mut Vec<OpLen>, oplen: OpLen) {
+    if let Some(last) = cigar.last_mut() {
+        if last.op == oplen.op {
+            last.len += oplen.len;
+            return;
+        }
+    }
+    cigar.push(oplen);
+}
+
+//Split kmer hits from get_coords where they cross the origin of a circular
+//genome, i.e. where they go past its end (or start, against the genome),
+//wrapping their positions into the genome. Hits past the ends of a linear
+//genome or across the origin are left out, as are hits that don't move along
+//the genome in the chain's direction.
+fn split_hits(
+    kmer_hit_coords: &[(i64, usize)],
+    chrom_len: i64,
+    strand_chrom: bool,
+    circular: bool,
+) -> Vec<Vec<(i64, usize)>> {
+    let mut parts: Vec<Vec<(i64, usize)>> = vec![];
+    let mut last_hit: Option<(i64, i64)> = None;
+    for (c1, c2) in kmer_hit_coords.iter() {
+        if !circular && (*c1 < 0 || *c1 >= chrom_len) {
+            continue;
+        }
+        //Number of times the hit is past the origin and its position
+        let lap = c1.div_euclid(chrom_len);
+        let pos = c1.rem_euclid(chrom_len);
+        if pos + constants::K as i64 > chrom_len {
+            continue;
+        }
+        if let Some((last_lap, last_pos)) = last_hit {
+            let progressing;
+            if lap == last_lap {
+                progressing = (strand_chrom && pos > last_pos) || (!strand_chrom && pos < last_pos);
+            } else {
+                progressing = (strand_chrom && lap > last_lap) || (!strand_chrom && lap < last_lap);
+            }
+            if !progressing {
+                continue;
+            }
+            if lap != last_lap {
+                parts.push(vec![]);
+            }
+        } else {
+            parts.push(vec![]);
+        }
+        parts.last_mut().unwrap().push((pos, *c2));
+        last_hit = Some((lap, pos));
+    }
+    return parts;
+}
+
+//Chain MAPQ as in minimap2 from the score of the chain, the best score of any
+//other chain and the number of anchors.
+pub fn chain_mapq(score: f64, second_score: f64, num_anchors: usize) -> u8 {
+    if score <= 1. {
+        return 0;
+    }
+    let mapq = 40.
+        * (1. - f64::max(second_score, 0.) / score)
+        * f64::min(1., num_anchors as f64 / 10.)
+        * score.ln();
+    if mapq < 0. {
+        return 0;
+    }
+    return f64::min(mapq, 60.) as u8;
+}
+
+//Chaining-only positions of a read on the genome of color, one per part of
+//the projected k-mer hits as in align_from_chain.
+pub fn paf_from_chain(
+    anchors: &Anchors,
+    chroms: &Vec<(DnaString, bool)>,
+    color: Color,
+    ref_graph: &Vec<KmerNode>,
+    read_seeds: &Vec<KmerNode>,
+    read_len: usize,
+    read_strand: bool,
+    chrom_names: &Vec<String>,
+    read_id: &String,
+    score: f64,
+    mapq: u8,
+    circular: bool,
+) -> Vec<PafInfo> {
+    if anchors.len() < 3 {
+        return vec![];
+    }
+    let chrom_index = chroms.len() - align::get_first_nonzero_bit(color) - 1;
+    let ref_chrom = &chroms[chrom_index].0;
+    let strand_chrom = chroms[chrom_index].1;
+    let (_ref_coords, kmer_hit_coords) =
+        align::get_coords(&anchors, &ref_graph, &read_seeds, color, &chroms);
+    let parts = split_hits(&kmer_hit_coords, ref_chrom.len() as i64, strand_chrom, circular);
+
+    let mut paf_infos = vec![];
+    for part in parts {
+        if part.len() < 3 {
+            continue;
+        }
+        let tstart = part.iter().map(|x| x.0).min().unwrap() as usize;
+        let tend = part.iter().map(|x| x.0).max().unwrap() as usize + constants::K;
+        let mut query_positions: Vec<usize> = part.iter().map(|x| x.1).collect();
+        query_positions.sort();
+        let qstart = query_positions[0];
+        let qend = query_positions.last().unwrap() + constants::K;
+        let mut matches = 0;
+        let mut covered_to = 0;
+        for pos in query_positions.iter() {
+            let start = usize::max(*pos, covered_to);
+            if pos + constants::K > start {
+                matches += pos + constants::K - start;
+                covered_to = pos + constants::K;
+            }
+        }
+        paf_infos.push(PafInfo {
+            qname: read_id.clone(),
+            qlen: read_len,
+            qstart: qstart,
+            qend: qend,
+            strand: read_strand == strand_chrom,
+            tname: chrom_names[chrom_index].clone(),
+            tlen: ref_chrom.len(),
+            tstart: tstart,
+            tend: tend,
+            matches: matches,
+            block_len: usize::max(qend - qstart, tend - tstart),
+            mapq: mapq,
+            num_anchors: part.len(),
+            score: score,
+            primary: true,
+        });
+    }
+    //The part spanning the most read is primary
+    paf_infos.sort_by(|x, y| (y.qend - y.qstart).cmp(&(x.qend - x.qstart)));
+    for paf_info in paf_infos.iter_mut().skip(1) {
+        paf_info.primary = false;
+    }
+    return paf_infos;
+}
+
+pub fn paf_string(paf_info: &PafInfo) -> String {
+    let strand;
+    if paf_info.strand {
+        strand = '+';
+    } else {
+        strand = '-';
+    }
+    let tp;
+    if paf_info.primary {
+        tp = 'P';
+    } else {
+        tp = 'S';
+    }
+    return format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\ttp:A:{}\tcm:i:{}\ts1:i:{}",
+        paf_info.qname,
+        paf_info.qlen,
+        paf_info.qstart,
+        paf_info.qend,
+        strand,
+        paf_info.tname,
+        paf_info.tlen,
+        paf_info.tstart,
+        paf_info.tend,
+        paf_info.matches,
+        paf_info.block_len,
+        paf_info.mapq,
+        tp,
+        paf_info.num_anchors,
+        paf_info.score as i64
+    );
+}
+
 pub fn align_from_chain(
     anchors: &Anchors,
     chroms: &Vec<(DnaString, bool)>,
+    chrom_gaps: &Vec<Vec<(usize, usize)>>,
     color: Color,
     ref_graph: &Vec<KmerNode>,
     read_seeds: &Vec<KmerNode>,
     read: &DnaString,
+    seq: &[u8],
     read_strand: bool,
     quals: &[u8],
     chrom_names: &Vec<String>,
     read_id: &String,
+    params: &Params,
+    circular: bool,
+    aligner: &dyn Aligner,
     //    headerview: &HeaderView,
     //    writer: &mut Writer,
-) -> Option<BamInfo> {
+) -> Vec<BamInfo> {
     println!("Aligning to genome corresponding to colour {} (or {})", color.trailing_zeros(), chrom_names[(chroms.len() as u32 - color.trailing_zeros() - 1) as usize]);
     if anchors.len() < 3 {
         println!("Less than 3 anchors, bad align");
         println!("Alignment score: NA");
-        return None;
+        return vec![];
     }
 
     let ref_chrom = &chroms[chroms.len() - align::get_first_nonzero_bit(color) - 1].0;
-    let strand_chrom = &chroms[chroms.len() - align::get_first_nonzero_bit(color) - 1].1;
-    let now = Instant::now();
+    let strand_chrom = chroms[chroms.len() - align::get_first_nonzero_bit(color) - 1].1;
+    let ref_chrom_name = &chrom_names[chroms.len() - align::get_first_nonzero_bit(color) - 1];
+    let ref_gaps = &chrom_gaps[chroms.len() - align::get_first_nonzero_bit(color) - 1];
     let (_ref_coords, kmer_hit_coords) =
         align::get_coords(&anchors, &ref_graph, &read_seeds, color, &chroms);
     if kmer_hit_coords.len() < 3 {
         println!("Less than 3 kmer hits, bad align");
         println!("Alignment score: NA");
-        return None;
+        return vec![];
     }
     //            dbg!(kmer_hit_coords[0]);
     //            dbg!(kmer_hit_coords[1]);
@@ -412,6 +892,9 @@ pub fn align_from_chain(
         } else {
             adj_c1 = *c1;
         }
+        if adj_c1 < 0 || adj_c1 + 16 > ref_chrom.len() as i64 {
+            continue;
+        }
         let node = &read_seeds[anchors[i].1 as usize];
         let r_node = &ref_graph[anchors[i].0 as usize];
         let d1 = read.slice(*c2, *c2 + 16).to_string();
@@ -442,240 +925,605 @@ pub fn align_from_chain(
         }
     }
 
+    //Each part of the hits is aligned as its own record. The read outside the
+    //hits of a linear genome is clipped.
+    let parts = split_hits(&kmer_hit_coords, ref_chrom.len() as i64, strand_chrom, circular);
+    if parts.len() > 1 {
+        println!("Alignment crosses the origin, splitting into {} records", parts.len());
+    }
+
+    let mut bam_infos = vec![];
+    for part in parts {
+        if part.len() < 3 {
+            println!("Less than 3 kmer hits in part, bad align");
+            continue;
+        }
+        if let Some(bam_info) = align_hits(
+            &part,
+            ref_chrom,
+            ref_gaps,
+            strand_chrom,
+            ref_chrom_name,
+            read,
+            seq,
+            read_strand,
+            quals,
+            read_id,
+            params,
+            aligner,
+        ) {
+            bam_infos.push(bam_info);
+        }
+    }
+    //The record aligning the most bases is primary, the rest supplementary.
+    //Each one lists the others in its SA tag.
+    let aligned_len = |x: &BamInfo| x.sequence.len() - x.soft_clips.0 - x.soft_clips.1;
+    bam_infos.sort_by(|x, y| aligned_len(y).cmp(&aligned_len(x)));
+    for bam_info in bam_infos.iter_mut().skip(1) {
+        bam_info.supplementary = true;
+    }
+    if bam_infos.len() > 1 {
+        let entries: Vec<String> = bam_infos.iter().map(sa_entry).collect();
+        for (i, bam_info) in bam_infos.iter_mut().enumerate() {
+            for (j, entry) in entries.iter().enumerate() {
+                if i != j {
+                    bam_info.sa.push_str(entry);
+                }
+            }
+        }
+    }
+    return bam_infos;
+}
+
+//Make the records of a chain after the best one secondary. As in minimap2, a
+//secondary alignment is only its primary record, without supplementary parts.
+pub fn mark_secondary(bam_infos: &mut Vec<BamInfo>) {
+    bam_infos.truncate(1);
+    for bam_info in bam_infos.iter_mut() {
+        bam_info.secondary = true;
+        bam_info.sa = String::new();
+    }
+}
+
+//Align the read to the reference window spanned by kmer_hit_coords, which
+//must lie inside ref_chrom and be in chain order. Bases in ref_gaps are
+//aligned as N. read is aligned and seq, the read as given, becomes SEQ.
+fn align_hits(
+    kmer_hit_coords: &[(i64, usize)],
+    ref_chrom: &DnaString,
+    ref_gaps: &Vec<(usize, usize)>,
+    strand_chrom: bool,
+    ref_chrom_name: &String,
+    read: &DnaString,
+    seq: &[u8],
+    read_strand: bool,
+    quals: &[u8],
+    read_id: &String,
+    params: &Params,
+    aligner: &dyn Aligner,
+) -> Option<BamInfo> {
+    let now = Instant::now();
     //GET THE REFERENCE STRING
     let ref_map_string;
     let a;
     let b;
-    if *strand_chrom {
+    if strand_chrom {
         a = kmer_hit_coords[0].0;
-        if kmer_hit_coords.last().unwrap().0 < a {
-            b = ref_chrom.len() as i64;
-            println!("Circular mapping b. Cut off TODO. Not implemented yet?");
-        } else {
-            b = kmer_hit_coords.last().unwrap().0 + 16;
-        }
-        if b as usize > ref_chrom.len() {
-            println!("End of chromosome mapping issue. Continue");
-            return None;
-        }
-        ref_map_string = ref_chrom.slice(a as usize, b as usize).to_string();
+        b = kmer_hit_coords.last().unwrap().0 + 16;
+        ref_map_string = graph_utils::genome_bytes(ref_chrom, ref_gaps, a as usize, b as usize);
     } else {
+        a = kmer_hit_coords.last().unwrap().0;
         b = kmer_hit_coords[0].0 + 16;
-        if kmer_hit_coords.last().unwrap().0 < 0 {
-            a = 0;
-            println!("Circular mapping a. Cut off TODO. Not implemented yet?");
-        } else {
-            a = kmer_hit_coords.last().unwrap().0;
-        }
-        if b as usize > ref_chrom.len() {
-            println!("End of chromosome mapping issue. Continue");
-            return None;
-        }
-        ref_map_string = ref_chrom.slice(a as usize, b as usize).rc().to_string();
+        ref_map_string =
+            revcomp(graph_utils::genome_bytes(ref_chrom, ref_gaps, a as usize, b as usize));
     }
-    let start_pos_chrom;
-    if read_strand == *strand_chrom {
-        start_pos_chrom = a;
-    } else {
-        start_pos_chrom = a;
-    }
-    let read_map_string;
-    let read_map_string_slice;
-    let qual_map_string;
+    let start_pos_chrom = a;
+    //The whole read in the orientation of the chain, with the quals to match,
+    //and the part of it between the outermost k-mer hits
+    let oriented_read;
+    let oriented_quals: Vec<u8>;
+    let core_start;
+    let core_end;
     if read_strand {
-        read_map_string = read
-            .slice(kmer_hit_coords[0].1, kmer_hit_coords.last().unwrap().1 + 16)
-            .to_string();
-        read_map_string_slice = read
-            .slice(kmer_hit_coords[0].1, kmer_hit_coords.last().unwrap().1 + 16);
-        qual_map_string = &quals[kmer_hit_coords[0].1..kmer_hit_coords.last().unwrap().1 + 16];
+        oriented_read = read.clone();
+        oriented_quals = quals.to_vec();
+        core_start = kmer_hit_coords[0].1;
+        core_end = kmer_hit_coords.last().unwrap().1 + 16;
     } else {
-        //Need to reverse the quals TODO
-        read_map_string = read
-            .slice(kmer_hit_coords.last().unwrap().1, kmer_hit_coords[0].1 + 16)
-            .rc()
-            .to_string();
-        read_map_string_slice = read
-            .slice(kmer_hit_coords.last().unwrap().1, kmer_hit_coords[0].1 + 16)
-            .rc();
-        qual_map_string = &quals[kmer_hit_coords.last().unwrap().1..kmer_hit_coords[0].1 + 16];
+        oriented_read = read.rc();
+        oriented_quals = quals.iter().rev().cloned().collect();
+        core_start = read.len() - kmer_hit_coords[0].1 - 16;
+        core_end = read.len() - kmer_hit_coords.last().unwrap().1;
     }
-
-    let block_align = true;
+    let read_map_string = oriented_read.slice(core_start, core_end).to_string();
 
     //ALIGNMENT
-    if block_align {
-        let now = Instant::now();
-        let block_size = 512;
-//        let block_size = 64;
-        let r_cpy = ref_map_string.clone();
-        let q_cpy = read_map_string.clone();
-
-        let r = PaddedBytes::from_string::<NucMatrix>(ref_map_string, block_size);
-        let q = PaddedBytes::from_string::<NucMatrix>(read_map_string.clone(), block_size);
-        let gaps = Gaps {
-            open: -2,
-            extend: -1,
-        };
+    let (cigar, core_score) = aligner.align(read_map_string.as_bytes(), &ref_map_string, params);
+    if cigar.is_empty() && !(read_map_string.is_empty() && ref_map_string.is_empty()) {
+        println!("Alignment failed");
+        println!("Alignment score: NA");
+        return None;
+    }
 
-        let nuc_mat: NucMatrix = NucMatrix::new_simple(1, -2);
-        let a = Block::<_, true, false>::align(&q, &r, &nuc_mat, gaps, block_size..=block_size, 50);
-
-        let res = a.res();
-        let cigar = a.trace().cigar(res.query_idx, res.reference_idx);
-//        let fmt_string = cigar.format(&q_cpy.as_bytes(), &r_cpy.as_bytes());
-//        println!("{}\n{}", fmt_string.0, fmt_string.1);
-//        println!("Aligning time: {}", now.elapsed().as_secs_f32());
-        println!("Alignment score: {}", res.score);
-        let ref_chrom_name = &chrom_names[chroms.len() - align::get_first_nonzero_bit(color) - 1];
-        let seq;
-        let cigar_vec: Vec<OpLen>;
-        if *strand_chrom{
-            seq = read_map_string;
-            cigar_vec = cigar.to_vec();
-        }
-        else{
-            seq = read_map_string_slice.rc().to_string();
-            cigar_vec = cigar.to_vec().into_iter().rev().collect();
-        }
-        let mapq_score = res.score as f64 / (q.len() + (read.len() - q.len()) * 2) as f64;
-        let mapq;
-        if mapq_score < 0.{
+    //Extend the alignment into the read ends outside the outermost k-mer
+    //hits. Whatever doesn't extend is soft clipped. The reference flanks
+    //are in the graph's orientation like ref_map_string.
+    let read_string = oriented_read.to_string();
+    let left_flank;
+    let right_flank;
+    let left_len = 2 * core_start;
+    let right_len = 2 * (read.len() - core_end);
+    if strand_chrom {
+        let left_start = i64::max(start_pos_chrom - left_len as i64, 0) as usize;
+        let right_end = usize::min(b as usize + right_len, ref_chrom.len());
+        left_flank =
+            graph_utils::genome_bytes(ref_chrom, ref_gaps, left_start, start_pos_chrom as usize);
+        right_flank = graph_utils::genome_bytes(ref_chrom, ref_gaps, b as usize, right_end);
+    } else {
+        let left_end = usize::min(b as usize + left_len, ref_chrom.len());
+        let right_start = i64::max(start_pos_chrom - right_len as i64, 0) as usize;
+        left_flank = revcomp(graph_utils::genome_bytes(ref_chrom, ref_gaps, b as usize, left_end));
+        right_flank = revcomp(graph_utils::genome_bytes(
+            ref_chrom,
+            ref_gaps,
+            right_start,
+            start_pos_chrom as usize,
+        ));
+    }
+    let left_query: Vec<u8> = read_string.as_bytes()[..core_start].iter().rev().cloned().collect();
+    let left_ref: Vec<u8> = left_flank.iter().rev().cloned().collect();
+    let (left_cigar, left_score, left_query_len, left_ref_len) =
+        aligner.extend(&left_query, &left_ref, params);
+    let (right_cigar, right_score, right_query_len, right_ref_len) = aligner.extend(
+        &read_string.as_bytes()[core_end..],
+        &right_flank,
+        params,
+    );
+
+    let mut cigar_vec: Vec<OpLen> = vec![];
+    for op in left_cigar.into_iter().rev() {
+        push_op(&mut cigar_vec, op);
+    }
+    for op in cigar {
+        push_op(&mut cigar_vec, op);
+    }
+    for op in right_cigar {
+        push_op(&mut cigar_vec, op);
+    }
+    let left_clip = core_start - left_query_len;
+    let right_clip = read.len() - core_end - right_query_len;
+    let score = core_score + left_score + right_score;
+    println!("Alignment score: {}", score);
+
+    //Put the record in the genome's orientation. SEQ keeps the read's own
+    //bases, so Ns stay N.
+    let record_seq;
+    if read_strand == strand_chrom {
+        record_seq = seq.to_ascii_uppercase();
+    } else {
+        record_seq = revcomp(seq.to_ascii_uppercase());
+    }
+    let record_quals;
+    let soft_clips;
+    let map_pos;
+    if strand_chrom {
+        record_quals = oriented_quals;
+        soft_clips = (left_clip, right_clip);
+        map_pos = start_pos_chrom - left_ref_len as i64;
+    } else {
+        record_quals = oriented_quals.into_iter().rev().collect();
+        cigar_vec.reverse();
+        soft_clips = (right_clip, left_clip);
+        map_pos = start_pos_chrom - right_ref_len as i64;
+    }
+    let aligned_len = read.len() - left_clip - right_clip;
+    let mapq_score = score as f64 / (aligned_len + (read.len() - aligned_len) * 2) as f64;
+    let mapq;
+    if mapq_score < 0.{
+        mapq = 0;
+    }
+    else{
+        let mapq_temp = mapq_score.ln() * 40. + 65.;
+        if mapq_temp < 0.{
             mapq = 0;
         }
         else{
-            let mapq_temp = mapq_score.ln() * 40. + 65.;
-            if mapq_temp < 0.{
-                mapq = 0;
-            }
-            else{
-                mapq = mapq_temp as i32;
-            }
+            mapq = mapq_temp as i32;
         }
-        let mapq = u8::min(mapq as u8, 60);
+    }
+    let mapq = u8::min(mapq as u8, 60);
 
-        let bam_info = BamInfo {
-            cigar: cigar_vec,
-            sequence: seq,
-            quals: qual_map_string.to_owned(),
-            qname: read_id.clone(),
-            strand: read_strand,
-            ref_name: ref_chrom_name.clone(),
-            map_pos: start_pos_chrom,
-            mapq: mapq,
-        };
+    let ref_span: usize = cigar_vec
+        .iter()
+        .filter(|x| x.op == Operation::M || x.op == Operation::D)
+        .map(|x| x.len)
+        .sum();
+    let ref_aligned = graph_utils::genome_bytes(
+        ref_chrom,
+        ref_gaps,
+        map_pos as usize,
+        map_pos as usize + ref_span,
+    );
+    let (edit_distance, md, cs, divergence) =
+        alignment_tags(&cigar_vec, &record_seq[soft_clips.0..], &ref_aligned);
 
-        println!("Read align time: {}", now.elapsed().as_secs_f32());
-        return Some(bam_info);
-    //        let bam_rec = align::get_bam_record(
-    //            bam_info,
-    //            &headerview,
-    //        );
-    //
-    //        writer.write(&bam_rec).unwrap();
-    } else {
-        //Testing out WFA alignment. Seems to be worse than blockaligner.
-        use libwfa::{affine_wavefront::*, bindings::*, mm_allocator::*, penalties::*};
+    let bam_info = BamInfo {
+        cigar: cigar_vec,
+        soft_clips: soft_clips,
+        supplementary: false,
+        secondary: false,
+        score: score,
+        edit_distance: edit_distance,
+        md: md,
+        cs: cs,
+        divergence: divergence,
+        sequence: String::from_utf8_lossy(&record_seq).to_string(),
+        quals: record_quals,
+        qname: read_id.clone(),
+        strand: read_strand == strand_chrom,
+        ref_name: ref_chrom_name.clone(),
+        map_pos: map_pos,
+        mapq: mapq,
+        sa: String::new(),
+    };
 
-        let alloc = MMAllocator::new(BUFFER_SIZE_1G as u64);
-        let min_wavefront_length = 10;
-        let max_distance_threshold = 50;
+    println!("Read align time: {}", now.elapsed().as_secs_f32());
+    return Some(bam_info);
+}
 
-        let pattern = ref_map_string.to_string();
-        let text = read_map_string.to_string();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aligner::BlockAligner;
+    use crate::constants;
+    use debruijn::kmer::Kmer16;
+    use rand::{Rng, SeedableRng};
+    use smallvec::SmallVec;
 
-        let mut penalties = AffinePenalties {
-            match_: 0,
-            mismatch: 4,
-            gap_opening: 6,
-            gap_extension: 2,
+    //Single colour node at position pos of its walk, with an edge of length
+    //dist to child
+    fn node(id: u32, pos: Option<usize>, child: Option<(u32, u32)>) -> KmerNode {
+        let mut node = KmerNode {
+            kmer: Kmer16::from_u64(0),
+            id: id,
+            order: id,
+            order_val: id,
+            color: 1,
+            child_nodes: SmallVec::new(),
+            child_edge_distance: SmallVec::new(),
+            canonical: true,
+            actual_ref_positions: SmallVec::new(),
+            repetitive: false,
+            primary_base: None,
+            span: 16,
         };
+        if let Some(pos) = pos {
+            node.actual_ref_positions.push(pos);
+        }
+        if let Some((child, dist)) = child {
+            node.child_nodes.push(child);
+            node.child_edge_distance.push((dist, (1, 0)));
+        }
+        return node;
+    }
 
-        let pat_len = pattern.as_bytes().len();
-        let text_len = text.as_bytes().len();
+    //Graph of a circular genome of length 1000 whose path isn't closed: the
+    //walk from the first anchor dead ends before the origin and get_coords
+    //restarts it at the next anchor, past the origin. On the reverse strand
+    //the genome runs against the graph.
+    fn open_circle(strand: bool) -> (Vec<KmerNode>, Vec<KmerNode>, Anchors) {
+        let positions;
+        if strand {
+            positions = [880, 920, 960, 10, 50, 90];
+        } else {
+            positions = [104, 64, 24, 974, 934, 894];
+        }
+        let mut ref_nodes = vec![];
+        let mut q_nodes = vec![];
+        for i in 0..6 {
+            let mut child = None;
+            if i != 2 && i != 5 {
+                child = Some((i as u32 + 1, 40));
+            }
+            let mut pos = None;
+            if i == 0 || i == 3 {
+                pos = Some(positions[i]);
+            }
+            ref_nodes.push(node(i as u32, pos, child));
+            q_nodes.push(node(i as u32, Some(20 + 40 * i + 10 * (i / 3)), None));
+        }
+        let anchors = (0..6).map(|i| (i, i)).collect();
+        return (ref_nodes, q_nodes, anchors);
+    }
 
-        let mut wavefronts = AffineWavefronts::new_reduced(
-            pat_len,
-            text_len,
-            &mut penalties,
-            min_wavefront_length,
-            max_distance_threshold,
-            &alloc,
+    #[test]
+    fn walks_restart_across_the_origin() {
+        let chroms = vec![(DnaString::from_acgt_bytes(&vec![b'A'; 1000]), true)];
+        let (ref_nodes, q_nodes, anchors) = open_circle(true);
+        let (interval, hits) = get_coords(&anchors, &ref_nodes, &q_nodes, 1, &chroms);
+        assert_eq!(interval, (880, 976));
+        assert_eq!(
+            hits,
+            vec![(880, 20), (920, 60), (960, 100), (1010, 150), (1050, 190), (1090, 230)]
+        );
+        assert_eq!(
+            split_hits(&hits, 1000, true, true),
+            vec![vec![(880, 20), (920, 60), (960, 100)], vec![(10, 150), (50, 190), (90, 230)]]
+        );
+        assert_eq!(
+            split_hits(&hits, 1000, true, false),
+            vec![vec![(880, 20), (920, 60), (960, 100)]]
         );
 
-        wavefronts
-            .align(pattern.as_bytes(), text.as_bytes())
-            .unwrap();
+        let chroms = vec![(DnaString::from_acgt_bytes(&vec![b'A'; 1000]), false)];
+        let (ref_nodes, q_nodes, anchors) = open_circle(false);
+        let (interval, hits) = get_coords(&anchors, &ref_nodes, &q_nodes, 1, &chroms);
+        assert_eq!(interval, (24, 120));
+        assert_eq!(
+            hits,
+            vec![(104, 20), (64, 60), (24, 100), (-26, 150), (-66, 190), (-106, 230)]
+        );
+        assert_eq!(
+            split_hits(&hits, 1000, false, true),
+            vec![vec![(104, 20), (64, 60), (24, 100)], vec![(974, 150), (934, 190), (894, 230)]]
+        );
+    }
 
-        let score = wavefronts.edit_cigar_score(&mut penalties);
-
-        println!("score: {}", score);
-
-        // The cigar can also be extracted as a byte vector
-        let cigar = wavefronts.cigar_bytes_raw();
-        let cg_str = std::str::from_utf8(&cigar).unwrap();
-
-        let mut op_vec = vec![];
-        let mut prev_char = 'A';
-        let mut running_len = 0;
-        for c in cg_str.chars() {
-            if c != prev_char && running_len != 0 {
-                let op;
-                if prev_char == 'X' {
-                    op = Operation::M;
-                } else if prev_char == 'M' {
-                    op = Operation::M;
-                } else if prev_char == 'I' {
-                    op = Operation::I;
-                } else if prev_char == 'D' {
-                    op = Operation::D;
-                } else {
-                    panic!("{}\n{}", prev_char, cg_str);
-                    op = Operation::Sentinel;
-                }
+    fn random_bases(rng: &mut rand::rngs::StdRng, len: usize, bases: &[u8]) -> Vec<u8> {
+        (0..len).map(|_| bases[rng.gen_range(0..bases.len())]).collect()
+    }
+
+    //Query length of a record's cigar, soft clips included
+    fn query_len(bam_info: &BamInfo) -> usize {
+        let aligned: usize = bam_info
+            .cigar
+            .iter()
+            .filter(|x| x.op == Operation::M || x.op == Operation::I)
+            .map(|x| x.len)
+            .sum();
+        aligned + bam_info.soft_clips.0 + bam_info.soft_clips.1
+    }
+
+    //A read whose ends can't align to the genome around them, since they are
+    //made of A and T and the genome there of C and G. The read also has an N
+    //and a lowercase base, which SEQ keeps.
+    #[test]
+    fn unalignable_ends_are_soft_clipped() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+        let params = constants::default_params();
+        let mut genome = random_bases(&mut rng, 500, b"CG");
+        genome.extend(random_bases(&mut rng, 600, b"ACGT"));
+        genome.extend(random_bases(&mut rng, 500, b"CG"));
+        let mut seq = random_bases(&mut rng, 40, b"AT");
+        seq.extend(&genome[500..1100]);
+        seq.extend(random_bases(&mut rng, 60, b"AT"));
+        seq[300] = b'N';
+        seq[301] = seq[301].to_ascii_lowercase();
+        let read = DnaString::from_acgt_bytes(&seq);
+        let quals: Vec<u8> = (0..seq.len()).map(|i| 33 + (i % 40) as u8).collect();
+        //k-mer hits every 100 bases of the shared part, skipping the N
+        let hit_offsets = [0, 100, 200, 350, 450, 584];
 
-                op_vec.push(OpLen {
-                    op: op,
-                    len: running_len,
-                });
-                running_len = 0;
-                prev_char = c;
+        for strand_chrom in [true, false] {
+            let chrom;
+            let hits: Vec<(i64, usize)>;
+            if strand_chrom {
+                chrom = genome.clone();
+                hits = hit_offsets.iter().map(|x| (500 + *x as i64, 40 + x)).collect();
+            } else {
+                chrom = revcomp(&genome);
+                hits = hit_offsets
+                    .iter()
+                    .map(|x| ((genome.len() - 516 - x) as i64, 40 + x))
+                    .collect();
             }
-            running_len += 1;
-            prev_char = c;
-        }
-        let op;
-        if prev_char == 'X' {
-            op = Operation::M;
-        } else if prev_char == 'M' {
-            op = Operation::M;
-        } else if prev_char == 'I' {
-            op = Operation::I;
-        } else if prev_char == 'D' {
-            op = Operation::D;
-        } else {
-            panic!("{}\n{}", prev_char, cg_str);
+            let bam_info = align_hits(
+                &hits,
+                &DnaString::from_acgt_bytes(&chrom),
+                &vec![],
+                strand_chrom,
+                &"genome".to_string(),
+                &read,
+                &seq,
+                true,
+                &quals,
+                &"read".to_string(),
+                &params,
+                &BlockAligner,
+            )
+            .unwrap();
+            assert_eq!(query_len(&bam_info), seq.len());
+            assert_eq!(bam_info.sequence.len(), seq.len());
+            if strand_chrom {
+                assert!(bam_info.strand);
+                assert_eq!(bam_info.soft_clips, (40, 60));
+                assert_eq!(bam_info.map_pos, 500);
+                assert_eq!(bam_info.sequence.as_bytes(), &seq.to_ascii_uppercase()[..]);
+                assert_eq!(bam_info.quals, quals);
+            } else {
+                assert!(!bam_info.strand);
+                assert_eq!(bam_info.soft_clips, (60, 40));
+                assert_eq!(bam_info.map_pos, (genome.len() - 1100) as i64);
+                assert_eq!(bam_info.sequence.as_bytes(), &revcomp(&seq.to_ascii_uppercase())[..]);
+                assert_eq!(bam_info.quals, quals.iter().rev().cloned().collect::<Vec<u8>>());
+            }
+            //The N is a mismatch in NM, MD and cs
+            let ref_base;
+            if strand_chrom {
+                ref_base = genome[760];
+            } else {
+                ref_base = revcomp(&[genome[760]])[0];
+            }
+            assert_eq!(bam_info.edit_distance, 1);
+            assert!(bam_info.md.contains(ref_base as char));
+            assert!(bam_info.cs.contains(&format!("*{}n", ref_base.to_ascii_lowercase() as char)));
         }
-        op_vec.push(OpLen {
-            op: op,
-            len: running_len,
-        });
+    }
 
-        let ref_chrom_name = &chrom_names[chroms.len() - align::get_first_nonzero_bit(color) - 1];
-        println!("cigar_str: {}", cg_str);
-        let bam_info = BamInfo {
-            cigar: op_vec,
-            sequence: read_map_string,
-            quals: qual_map_string.to_owned(),
-            qname: read_id.clone(),
-            strand: read_strand,
-            ref_name: ref_chrom_name.clone(),
-            map_pos: start_pos_chrom,
-            mapq: 60,
-        };
+    #[test]
+    fn tags_from_cigar() {
+        let cigar = vec![
+            OpLen { op: Operation::M, len: 4 },
+            OpLen { op: Operation::I, len: 1 },
+            OpLen { op: Operation::M, len: 3 },
+            OpLen { op: Operation::D, len: 2 },
+            OpLen { op: Operation::M, len: 3 },
+        ];
+        let (edit_distance, md, cs, divergence) =
+            alignment_tags(&cigar, b"ACGTGAGATAC", b"ACGTAGCTTTAC");
+        assert_eq!(edit_distance, 4);
+        assert_eq!(md, "6C0^TT3");
+        assert_eq!(cs, ":4+g:2*ca-tt:3");
+        assert_eq!(divergence, 0.25);
+    }
+
+    //A read across the origin of the open circle is split into a primary and
+    //a supplementary record that list each other in SA
+    #[test]
+    fn split_records_have_sa_tags() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+        let genome = random_bases(&mut rng, 1000, b"ACGT");
+        let chroms = vec![(DnaString::from_acgt_bytes(&genome), true)];
+        let mut seq = genome[860..].to_vec();
+        seq.extend(&genome[..120]);
+        let (ref_nodes, q_nodes, anchors) = open_circle(true);
+        let bam_infos = align_from_chain(
+            &anchors,
+            &chroms,
+            &vec![vec![]],
+            1,
+            &ref_nodes,
+            &q_nodes,
+            &DnaString::from_acgt_bytes(&seq),
+            &seq,
+            true,
+            &vec![b'I'; seq.len()],
+            &vec!["genome".to_string()],
+            &"read".to_string(),
+            &constants::default_params(),
+            true,
+            &BlockAligner,
+        );
+        assert_eq!(bam_infos.len(), 2);
+        assert!(!bam_infos[0].supplementary);
+        assert!(bam_infos[1].supplementary);
+        assert_eq!(bam_infos[0].map_pos, 860);
+        assert_eq!(hts_cigar(&bam_infos[0]).to_string(), "140M120S");
+        assert_eq!(bam_infos[1].map_pos, 0);
+        assert_eq!(hts_cigar(&bam_infos[1]).to_string(), "140S120M");
+        assert_eq!(
+            bam_infos[0].sa,
+            format!("genome,1,+,140S120M,{},0;", bam_infos[1].mapq)
+        );
+        assert_eq!(
+            bam_infos[1].sa,
+            format!("genome,861,+,140M120S,{},0;", bam_infos[0].mapq)
+        );
 
-        println!("Read align time: {}", now.elapsed().as_secs_f32());
-        return Some(bam_info);
+        let mut bam_infos = bam_infos;
+        mark_secondary(&mut bam_infos);
+        assert_eq!(bam_infos.len(), 1);
+        assert!(bam_infos[0].secondary && !bam_infos[0].supplementary);
+        assert!(bam_infos[0].sa.is_empty());
     }
 
+    #[test]
+    fn hits_split_at_origin() {
+        let hits = vec![(80, 0), (90, 10), (100, 20), (110, 30), (120, 40)];
+        let parts = split_hits(&hits, 110, true, true);
+        assert_eq!(parts, vec![vec![(80, 0), (90, 10)], vec![(0, 30), (10, 40)]]);
+        let parts = split_hits(&hits, 110, true, false);
+        assert_eq!(parts, vec![vec![(80, 0), (90, 10)]]);
+        let reverse_hits = vec![(20, 0), (10, 10), (0, 20), (-20, 30)];
+        let parts = split_hits(&reverse_hits, 110, false, true);
+        assert_eq!(parts, vec![vec![(20, 0), (10, 10), (0, 20)], vec![(90, 30)]]);
+        //Hits going back along the genome are dropped rather than split off
+        let hits = vec![(10, 0), (30, 10), (20, 20), (40, 30), (5, 40), (50, 50)];
+        let parts = split_hits(&hits, 110, true, true);
+        assert_eq!(parts, vec![vec![(10, 0), (30, 10), (40, 30), (50, 50)]]);
+    }
+
+    #[test]
+    fn chain_mapq_from_scores() {
+        assert_eq!(chain_mapq(1000., 0., 50), 60);
+        assert_eq!(chain_mapq(1000., 1000., 50), 0);
+        assert_eq!(chain_mapq(100., 80., 5), 18);
+        assert_eq!(chain_mapq(0., 0., 0), 0);
+    }
+
+    #[test]
+    fn read_group_from_line() {
+        let fields = read_group_fields("@RG\\tID:s1\\tSM:sample one").unwrap();
+        assert_eq!(
+            fields,
+            vec![
+                ("ID".to_string(), "s1".to_string()),
+                ("SM".to_string(), "sample one".to_string())
+            ]
+        );
+        assert_eq!(read_group_fields("@RG\tID:s1\tPL:ONT").unwrap().len(), 2);
+        assert!(read_group_fields("@RG\\tSM:bar").is_err());
+        assert!(read_group_fields("@RG\\tID:foo\\tbar").is_err());
+    }
+
+    #[test]
+    fn command_line_tabs_and_newlines_are_escaped() {
+        let chroms = vec![(DnaString::from_acgt_bytes(b"ACGTACGT"), true)];
+        let header = bam_header(
+            &chroms,
+            &vec![vec![]],
+            &vec!["a".to_string()],
+            false,
+            &vec![],
+            "chrom_mini_graph map --rg @RG\tID:x\nfoo",
+            &vec![],
+        );
+        let text = String::from_utf8(header.to_bytes()).unwrap();
+        let pg_line = text.lines().find(|x| x.starts_with("@PG")).unwrap();
+        assert!(pg_line.ends_with("CL:chrom_mini_graph map --rg @RG\\tID:x\\nfoo"));
+        assert_eq!(text.lines().count(), 3);
+    }
+
+    fn unaligned_mapping(aligned_color: Option<Color>) -> Mapping {
+        Mapping {
+            chain: 0,
+            strand: true,
+            score: 0.,
+            anchors: vec![],
+            paths: vec![],
+            segments: vec![],
+            primary_ref_chain: false,
+            aligned_color: aligned_color,
+            bam_info: vec![],
+            gaf_info: None,
+            paf_info: vec![],
+        }
+    }
+
+    #[test]
+    fn unmapped_records_keep_the_read() {
+        let seq = b"acgtNNACGTtt";
+        let quals = b"!#5I?+!#5I?+";
+        let cases = vec![
+            (vec![], "no_chain"),
+            (vec![unaligned_mapping(None)], "no_chain"),
+            (vec![unaligned_mapping(None), unaligned_mapping(Some(1))], "few_anchors"),
+        ];
+        for (mappings, expected) in cases {
+            let reason = unmapped_reason(&mappings).unwrap();
+            assert_eq!(reason, expected);
+            let rec = get_unmapped_bam_record("read", seq, quals, reason, Some("rg1"));
+            assert_eq!(rec.flags(), 4);
+            assert_eq!(rec.tid(), -1);
+            assert_eq!(rec.pos(), -1);
+            assert_eq!(rec.mtid(), -1);
+            assert_eq!(rec.mpos(), -1);
+            assert_eq!(rec.qname(), b"read");
+            assert_eq!(rec.seq().as_bytes(), b"ACGTNNACGTTT".to_vec());
+            let scaled_quals: Vec<u8> = quals.iter().map(|x| x - 33).collect();
+            assert_eq!(rec.qual(), &scaled_quals[..]);
+            assert_eq!(rec.aux(b"XU").unwrap(), Aux::String(expected));
+            assert_eq!(rec.aux(b"RG").unwrap(), Aux::String("rg1"));
+        }
+    }
 }
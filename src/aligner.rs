@@ -0,0 +1,222 @@
+use crate::data_structs::Params;
+use block_aligner::cigar::*;
+use block_aligner::scan_block::*;
+use block_aligner::scores::*;
+use libwfa::{affine_wavefront::*, bindings::*, mm_allocator::*, penalties::*};
+
+//Base level aligner used by align::align_from_chain. Queries and references
+//are ACGT bytes and scores follow the match, mismatch and gap scores in
+//params, so records look the same whichever aligner made them.
+pub trait Aligner: Send + Sync {
+    //Global alignment of query against reference. Returns the cigar and score,
+    //or an empty cigar if the alignment failed.
+    fn align(&self, query: &[u8], reference: &[u8], params: &Params) -> (Vec<OpLen>, i32);
+
+    //Extension alignment of query against reference from their starts, ends
+    //free, stopping once the score drops x_drop below the best. Returns the
+    //cigar, score and query and reference lengths of the best scoring prefix.
+    fn extend(
+        &self,
+        query: &[u8],
+        reference: &[u8],
+        params: &Params,
+    ) -> (Vec<OpLen>, i32, usize, usize) {
+        return block_extend(query, reference, params);
+    }
+}
+
+//Adaptive banded alignment with block aligner
+pub struct BlockAligner;
+
+//Gap-affine wavefront alignment with libwfa. The wavefronts are reduced as
+//params.wfa_min_wavefront_length and params.wfa_max_distance_threshold ask,
+//which is a heuristic, so the best alignment can be missed. libwfa has no
+//extension mode, so read ends are extended with block aligner.
+pub struct WfaAligner;
+
+thread_local! {
+    //libwfa's memory allocator, made once for each thread that aligns
+    static WFA_ALLOCATOR: MMAllocator = MMAllocator::new(BUFFER_SIZE_1G as u64);
+}
+
+//Aligner for the --aligner names
+pub fn aligner_from_name(name: &str) -> Option<Box<dyn Aligner>> {
+    if name == "block" {
+        return Some(Box::new(BlockAligner));
+    } else if name == "wfa" {
+        return Some(Box::new(WfaAligner));
+    } else {
+        return None;
+    }
+}
+
+fn block_scores(params: &Params) -> (NucMatrix, Gaps) {
+    let gaps = Gaps {
+        open: -params.gap_open,
+        extend: -params.gap_extend,
+    };
+    let nuc_mat: NucMatrix = NucMatrix::new_simple(params.match_score, -params.mismatch);
+    return (nuc_mat, gaps);
+}
+
+fn block_extend(query: &[u8], reference: &[u8], params: &Params) -> (Vec<OpLen>, i32, usize, usize) {
+    if query.is_empty() || reference.is_empty() {
+        return (vec![], 0, 0, 0);
+    }
+    let block_size = params.block_size;
+    let q = PaddedBytes::from_bytes::<NucMatrix>(query, block_size);
+    let r = PaddedBytes::from_bytes::<NucMatrix>(reference, block_size);
+    let (nuc_mat, gaps) = block_scores(params);
+    let a = Block::<_, true, true>::align(
+        &q,
+        &r,
+        &nuc_mat,
+        gaps,
+        block_size..=block_size,
+        params.x_drop,
+    );
+    let res = a.res();
+    if res.score <= 0 {
+        return (vec![], 0, 0, 0);
+    }
+    let cigar = a.trace().cigar(res.query_idx, res.reference_idx);
+    return (cigar.to_vec(), res.score, res.query_idx, res.reference_idx);
+}
+
+impl Aligner for BlockAligner {
+    fn align(&self, query: &[u8], reference: &[u8], params: &Params) -> (Vec<OpLen>, i32) {
+        let block_size = params.block_size;
+        let q = PaddedBytes::from_bytes::<NucMatrix>(query, block_size);
+        let r = PaddedBytes::from_bytes::<NucMatrix>(reference, block_size);
+        let (nuc_mat, gaps) = block_scores(params);
+        let a = Block::<_, true, false>::align(
+            &q,
+            &r,
+            &nuc_mat,
+            gaps,
+            block_size..=block_size,
+            params.x_drop,
+        );
+        let res = a.res();
+        let cigar = a.trace().cigar(res.query_idx, res.reference_idx);
+        return (cigar.to_vec(), res.score);
+    }
+}
+
+impl Aligner for WfaAligner {
+    fn align(&self, query: &[u8], reference: &[u8], params: &Params) -> (Vec<OpLen>, i32) {
+        if query.is_empty() || reference.is_empty() {
+            let mut cigar = vec![];
+            if !query.is_empty() {
+                cigar.push(OpLen { op: Operation::I, len: query.len() });
+            }
+            if !reference.is_empty() {
+                cigar.push(OpLen { op: Operation::D, len: reference.len() });
+            }
+            let gap_len = query.len() + reference.len();
+            let mut score = 0;
+            if gap_len > 0 {
+                score = -(params.gap_open as i32) - (gap_len as i32 - 1) * params.gap_extend as i32;
+            }
+            return (cigar, score);
+        }
+        return WFA_ALLOCATOR.with(|alloc| wfa_align(query, reference, params, alloc));
+    }
+}
+
+//An alignment that libwfa fails comes back with an empty cigar
+fn wfa_align(
+    query: &[u8],
+    reference: &[u8],
+    params: &Params,
+    alloc: &MMAllocator,
+) -> (Vec<OpLen>, i32) {
+    //WFA minimises penalties with a free match. Twice the score of a
+    //global alignment is match * (query + reference length) minus these
+    //penalties, so both aligners score an alignment the same.
+    let mut penalties = AffinePenalties {
+        match_: 0,
+        mismatch: 2 * (params.match_score as i32 + params.mismatch as i32),
+        gap_opening: 2 * (params.gap_open as i32 - params.gap_extend as i32),
+        gap_extension: 2 * params.gap_extend as i32 + params.match_score as i32,
+    };
+
+    let mut wavefronts = AffineWavefronts::new_reduced(
+        reference.len(),
+        query.len(),
+        &mut penalties,
+        params.wfa_min_wavefront_length,
+        params.wfa_max_distance_threshold,
+        alloc,
+    );
+
+    if let Err(error) = wavefronts.align(reference, query) {
+        println!("WFA alignment failed: {}", error);
+        return (vec![], 0);
+    }
+
+    let penalty = wavefronts.edit_cigar_score(&mut penalties).abs() as i32;
+    let score = (params.match_score as i32 * (query.len() + reference.len()) as i32 - penalty) / 2;
+
+    // The cigar has one character per operation, X for mismatches
+    let cigar = wavefronts.cigar_bytes_raw();
+    let mut op_vec: Vec<OpLen> = vec![];
+    for c in cigar.iter() {
+        let op;
+        if *c == b'M' || *c == b'X' {
+            op = Operation::M;
+        } else if *c == b'I' {
+            op = Operation::I;
+        } else if *c == b'D' {
+            op = Operation::D;
+        } else {
+            panic!("{}", String::from_utf8_lossy(&cigar));
+        }
+        if let Some(last) = op_vec.last_mut() {
+            if last.op == op {
+                last.len += 1;
+                continue;
+            }
+        }
+        op_vec.push(OpLen { op: op, len: 1 });
+    }
+    return (op_vec, score);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants;
+    use crate::test_utils;
+    use rand::{Rng, SeedableRng};
+
+    //Query and reference lengths of a cigar
+    fn cigar_lens(cigar: &[OpLen]) -> (usize, usize) {
+        let mut query_len = 0;
+        let mut ref_len = 0;
+        for oplen in cigar.iter() {
+            if oplen.op != Operation::D {
+                query_len += oplen.len;
+            }
+            if oplen.op != Operation::I {
+                ref_len += oplen.len;
+            }
+        }
+        (query_len, ref_len)
+    }
+
+    #[test]
+    fn block_and_wfa_agree() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+        let params = constants::default_params();
+        for seed in 0..10 {
+            let reference: Vec<u8> = (0..300).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let query = test_utils::mutate(&reference, 0.05, seed);
+            let (block_cigar, block_score) = BlockAligner.align(&query, &reference, &params);
+            let (wfa_cigar, wfa_score) = WfaAligner.align(&query, &reference, &params);
+            assert_eq!(block_score, wfa_score);
+            assert_eq!(cigar_lens(&block_cigar), (query.len(), reference.len()));
+            assert_eq!(cigar_lens(&wfa_cigar), (query.len(), reference.len()));
+        }
+    }
+}
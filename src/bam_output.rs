@@ -0,0 +1,247 @@
+use crate::graph_utils;
+use bio::io::fasta;
+use debruijn::dna_string::DnaString;
+use rust_htslib::bam::header::Header;
+use rust_htslib::bam::index;
+use rust_htslib::bam::record::Record;
+use rust_htslib::bam::{CompressionLevel, Format, HeaderView, Read, Reader, Writer};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+
+//Writes records to a SAM, BAM or CRAM file. CRAM is compressed against the
+//genomes, which are written next to the output as <output>.fa. When sorting,
+//records are held until finish. Once they take up more than sort_memory bytes
+//they are sorted and spilled to temporary BAM files, which finish merges.
+pub struct BamOutput {
+    path: String,
+    format: Format,
+    header: Header,
+    headerview: HeaderView,
+    writer: Writer,
+    sort: bool,
+    sort_memory: usize,
+    buffer: Vec<Record>,
+    buffer_bytes: usize,
+    spill_files: Vec<String>,
+}
+
+pub fn format_from_name(name: &str) -> Option<Format> {
+    if name == "sam" {
+        return Some(Format::Sam);
+    } else if name == "bam" {
+        return Some(Format::Bam);
+    } else if name == "cram" {
+        return Some(Format::Cram);
+    } else {
+        return None;
+    }
+}
+
+//Coordinate order as in samtools sort; unmapped records go last.
+fn sort_key(rec: &Record) -> (u32, i64, bool) {
+    return (rec.tid() as u32, rec.pos(), rec.is_reverse());
+}
+
+impl BamOutput {
+    pub fn new(
+        path: &str,
+        format: Format,
+        header: Header,
+        chroms: &Vec<(DnaString, bool)>,
+        chrom_gaps: &Vec<Vec<(usize, usize)>>,
+        chrom_names: &Vec<String>,
+        sort: bool,
+        sort_memory: usize,
+    ) -> BamOutput {
+        let mut writer = Writer::from_path(path, &header, format).unwrap();
+        if let Format::Cram = format {
+            let reference_name = format!("{}.fa", path);
+            let mut fasta_writer = fasta::Writer::to_file(&reference_name).unwrap();
+            for (i, (chrom, _strand)) in chroms.iter().enumerate() {
+                let bases = graph_utils::genome_bytes(chrom, &chrom_gaps[i], 0, chrom.len());
+                fasta_writer.write(&chrom_names[i], None, &bases).unwrap();
+            }
+            fasta_writer.flush().unwrap();
+            writer.set_reference(&reference_name).unwrap();
+        }
+        BamOutput {
+            path: path.to_string(),
+            format: format,
+            headerview: HeaderView::from_header(&header),
+            header: header,
+            writer: writer,
+            sort: sort,
+            sort_memory: sort_memory,
+            buffer: vec![],
+            buffer_bytes: 0,
+            spill_files: vec![],
+        }
+    }
+
+    pub fn headerview(&self) -> &HeaderView {
+        return &self.headerview;
+    }
+
+    pub fn write(&mut self, rec: Record) {
+        if !self.sort {
+            self.writer.write(&rec).unwrap();
+            return;
+        }
+        self.buffer_bytes += rec.inner().l_data as usize + std::mem::size_of::<Record>();
+        self.buffer.push(rec);
+        if self.buffer_bytes > self.sort_memory {
+            self.spill();
+        }
+    }
+
+    fn spill(&mut self) {
+        let spill_name = format!("{}.tmp.{:04}.bam", self.path, self.spill_files.len());
+        println!(
+            "Spilling {} sorted records to {}",
+            self.buffer.len(),
+            spill_name
+        );
+        self.buffer.sort_by_key(sort_key);
+        let mut spill_writer = Writer::from_path(&spill_name, &self.header, Format::Bam).unwrap();
+        spill_writer
+            .set_compression_level(CompressionLevel::Fastest)
+            .unwrap();
+        for rec in self.buffer.iter() {
+            spill_writer.write(rec).unwrap();
+        }
+        self.buffer = vec![];
+        self.buffer_bytes = 0;
+        self.spill_files.push(spill_name);
+    }
+
+    //Write out held records, merging any spilled ones, close the file and
+    //index it if index_type is given. Only sorted BAM and CRAM files can be
+    //indexed; a CRAM file gets a .crai index whatever the type.
+    pub fn finish(mut self, index_type: Option<index::Type>) {
+        if self.sort {
+            if self.spill_files.is_empty() {
+                self.buffer.sort_by_key(sort_key);
+                for rec in self.buffer.iter() {
+                    self.writer.write(rec).unwrap();
+                }
+            } else {
+                if !self.buffer.is_empty() {
+                    self.spill();
+                }
+                let mut readers = vec![];
+                let mut heads = vec![];
+                let mut heap = BinaryHeap::new();
+                for (i, spill_name) in self.spill_files.iter().enumerate() {
+                    let mut reader = Reader::from_path(spill_name).unwrap();
+                    let mut rec = Record::new();
+                    if let Some(result) = reader.read(&mut rec) {
+                        result.unwrap();
+                        heap.push(Reverse((sort_key(&rec), i)));
+                    }
+                    readers.push(reader);
+                    heads.push(rec);
+                }
+                while let Some(Reverse((_key, i))) = heap.pop() {
+                    self.writer.write(&heads[i]).unwrap();
+                    if let Some(result) = readers[i].read(&mut heads[i]) {
+                        result.unwrap();
+                        heap.push(Reverse((sort_key(&heads[i]), i)));
+                    }
+                }
+                for spill_name in self.spill_files.iter() {
+                    fs::remove_file(spill_name).unwrap();
+                }
+            }
+        }
+
+        //Dropping the writer flushes and closes the file before indexing
+        let path = self.path;
+        let format = self.format;
+        drop(self.writer);
+        if let Some(index_type) = index_type {
+            if let Format::Sam = format {
+                panic!("SAM output can't be indexed");
+            }
+            if !self.sort {
+                panic!("Only sorted output can be indexed");
+            }
+            index::build(&path, None, index_type, 1).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align;
+    use rand::{Rng, SeedableRng};
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn spilled_records_are_merged_in_order() {
+        let chroms = vec![
+            (DnaString::from_acgt_bytes(&vec![b'A'; 1000]), true),
+            (DnaString::from_acgt_bytes(&vec![b'C'; 1000]), true),
+        ];
+        let chrom_gaps = vec![vec![], vec![]];
+        let chrom_names = vec!["a".to_string(), "b".to_string()];
+        let header =
+            align::bam_header(&chroms, &chrom_gaps, &chrom_names, true, &vec![], "test", &vec![]);
+        let path = std::env::temp_dir()
+            .join(format!("spill_test_{}.bam", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        //Room for only a few records at a time
+        let sort_memory = 20 * std::mem::size_of::<Record>();
+        let mut output = BamOutput::new(
+            &path,
+            Format::Bam,
+            header,
+            &chroms,
+            &chrom_gaps,
+            &chrom_names,
+            true,
+            sort_memory,
+        );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+        let num_records = 500;
+        for i in 0..num_records {
+            let mut rec = Record::new();
+            let cigar = CigarString(vec![Cigar::Match(4)]);
+            rec.set(format!("r{}", i).as_bytes(), Some(&cigar), b"ACGT", &[30; 4]);
+            if rng.gen_bool(0.1) {
+                rec.set_tid(-1);
+                rec.set_pos(-1);
+                rec.set_unmapped();
+            } else {
+                rec.set_tid(rng.gen_range(0..2));
+                rec.set_pos(rng.gen_range(0..996));
+                if rng.gen_bool(0.5) {
+                    rec.set_reverse();
+                }
+            }
+            output.write(rec);
+        }
+        assert!(output.spill_files.len() > 2);
+        let spill_files = output.spill_files.clone();
+        output.finish(None);
+
+        let mut reader = Reader::from_path(&path).unwrap();
+        let mut keys = vec![];
+        for rec in reader.records() {
+            keys.push(sort_key(&rec.unwrap()));
+        }
+        assert_eq!(keys.len(), num_records);
+        for i in 1..keys.len() {
+            assert!(keys[i - 1] <= keys[i]);
+        }
+        assert_eq!(keys.last().unwrap().0, u32::MAX);
+        for spill_name in spill_files.iter() {
+            assert!(!std::path::Path::new(spill_name).exists());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,20 +3,26 @@ use bio::io::{fasta, fastq};
 use block_aligner::scan_block::*;
 use block_aligner::scores::*;
 use chrom_mini_graph::align;
+use chrom_mini_graph::aligner;
+use chrom_mini_graph::bam_output;
 use chrom_mini_graph::chain;
 use chrom_mini_graph::constants;
-use chrom_mini_graph::coord_chain;
-use chrom_mini_graph::data_structs::KmerNode;
+use chrom_mini_graph::data_structs::{
+    ColourChaining, KmerNode, Params, SeedMethod, SeedParams, SeqGraph,
+};
 use chrom_mini_graph::deconvolution;
+use chrom_mini_graph::graph_align;
 use chrom_mini_graph::graph_utils;
+use chrom_mini_graph::mapper;
 use chrom_mini_graph::seeding_methods_bit;
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use debruijn::dna_string::*;
 use debruijn::kmer::Kmer16;
 use debruijn::Kmer;
 use debruijn::Mer;
 use fxhash::{FxHashMap, FxHashSet};
 use rayon::prelude::*;
+use rust_htslib::bam::index;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
@@ -44,7 +50,7 @@ fn main() {
                 arg(
                     Arg::with_name("output")
                         .short("o")
-                        .help("Name of output chromatic reference graph. Produces a .json and .bin file. (Default: serialized_mini_graph)")
+                        .help("Name of output chromatic reference graph. Produces a .json, .bin and .gfa file. (Default: serialized_mini_graph)")
                         .takes_value(true),
                 ).
                 arg(
@@ -52,11 +58,64 @@ fn main() {
                         .short("s")
                         .help("Use syncmers. (Default: use minimizers)")
                 ).
+                arg(
+                    Arg::with_name("closed")
+                        .long("closed")
+                        .help("Use closed syncmers instead of open syncmers with -s. (Default: open syncmers)")
+                ).
+                arg(
+                    Arg::with_name("syncmer_s")
+                        .long("syncmer-s")
+                        .help("s-mer length for syncmers, at most k = 16. (Default: 8)")
+                        .takes_value(true)
+                ).
+                arg(
+                    Arg::with_name("syncmer_t")
+                        .long("syncmer-t")
+                        .help("Offset of the smallest s-mer for open syncmers, between 1 and k - s + 1. (Default: (k - s + 2) / 2)")
+                        .takes_value(true)
+                ).
+                arg(
+                    Arg::with_name("strobemer")
+                        .long("strobemer")
+                        .help("Use randstrobes of two k/2-mers, for reads with many indels. First strobes are chosen as minimizers over -w. (Default: use minimizers)")
+                        .conflicts_with("syncmer")
+                ).
+                arg(
+                    Arg::with_name("strobe_w_min")
+                        .long("strobe-w-min")
+                        .help("Smallest offset of the second strobe from the first, at least k/2. (Default: 8)")
+                        .takes_value(true)
+                        .validator(|v| strobe_window(&v, constants::K / 2))
+                ).
+                arg(
+                    Arg::with_name("strobe_w_max")
+                        .long("strobe-w-max")
+                        .help("Largest offset of the second strobe from the first, at least --strobe-w-min. (Default: 40)")
+                        .takes_value(true)
+                        .validator(|v| strobe_window(&v, constants::K / 2))
+                ).
+                arg(
+                    Arg::with_name("hpc")
+                        .long("hpc")
+                        .help("Seed on homopolymer compressed sequence, for reads with homopolymer length errors such as PacBio CLR and ONT. Not available with --strobemer.")
+                        .conflicts_with("strobemer")
+                ).
+                arg(
+                    Arg::with_name("soft_mask")
+                        .long("soft-mask")
+                        .help("Don't seed lowercase soft-masked bases. Non-ACGT bases are never seeded.")
+                ).
                 arg(
                     Arg::with_name("circular")
                         .short("c")
                         .help("Assume that the genomes are circular. (Default: not circular)")
                 ).
+                arg(
+                    Arg::with_name("chain_heuristic")
+                        .short("d")
+                        .help("Use linearization heuristic instead of DAG-aware heuristic. (Default: use chain heuristic)")
+                ).
                 arg(
                     Arg::with_name("mask")
                         .short("m")
@@ -72,16 +131,57 @@ fn main() {
                 arg(
                     Arg::with_name("minimizer_weighting")
                         .short("r")
-                        .help("marbl minimizer weighting file for k = 16. (Default: none)")
+                        .help("marbl minimizer weighting file for k = 16, used instead of counting k-mers over the input genomes. (Default: none)")
                         .takes_value(true)
                 ).
                 arg(
                     Arg::with_name("w")
                         .short("w")
-                        .help("w value (testing).")
+                        .long("window")
+                        .help("Minimizer window size. (Default: 16, or set by -P)")
                         .takes_value(true)
-                        .hidden(true)
+                ).
+                arg(
+                    Arg::with_name("no_genomes")
+                        .short("n")
+                        .help("Don't store the linear genomes in the .bin file; they are rebuilt from the sequence on the graph's edges when mapping. (Default: store genomes)")
+                )
+                .args(&param_args())
+        )
+        .subcommand(
+            SubCommand::with_name("paint")
+                .about("Segment assemblies along their length by the most similar genomes in the graph.")
+                .version("0.1")
+                .arg(
+                    Arg::with_name("reference_graph")
+                        .required(true)
+                        .index(1)
+                        .help("Reference graph (.bin) output from the generate subcommand. E.g. serialized_mini_graph.bin"),
+                ).
+                arg(
+                    Arg::with_name("assemblies")
+                        .required(true)
+                        .index(2)
+                        .multiple(true)
+                        .help("Assembly fasta files to paint. Every contig is painted separately."),
+                ).
+                arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .help("Name of output BED file. (Default: paint.bed)")
+                        .takes_value(true),
+                ).
+                arg(
+                    Arg::with_name("circular")
+                        .short("c")
+                        .help("Assume that the genomes are circular. (Default: not circular)")
+                ).
+                arg(
+                    Arg::with_name("chain_heuristic")
+                        .short("d")
+                        .help("Use linearization heuristic instead of DAG-aware heuristic. (Default: use chain heuristic)")
                 )
+                .args(&param_args())
         )
         .subcommand(
             SubCommand::with_name("map")
@@ -105,16 +205,16 @@ fn main() {
                   .value_name("INT")
                   .takes_value(true)
                 ).
-                arg(
-                    Arg::with_name("syncmer")
-                        .short("s")
-                        .help("Use syncmers. (Default: use minimizers)")
-                ).
                 arg(
                     Arg::with_name("chain_heuristic")
                         .short("d")
                         .help("Use linearization heuristic instead of DAG-aware heuristic. (Default: use chain heuristic)")
                 ).
+                arg(
+                    Arg::with_name("circular")
+                        .short("c")
+                        .help("Assume that the genomes are circular. (Default: not circular)")
+                ).
                 arg(
                     Arg::with_name("dont_output_stuff")
                         .short("u")
@@ -128,65 +228,115 @@ fn main() {
                 arg(
                     Arg::with_name("bam_name")
                         .short("b")
-                        .help("Name of output bam file. (Default: output.bam)")
+                        .help("Name of output bam file. (Default: output.bam, output.sam or output.cram)")
                         .takes_value(true),
                 ).
                 arg(
-                    Arg::with_name("short_reads")
-                        .short("x")
-                        .help("Short read parameters. (Default: Long read params.)")
-                        .hidden(true)
+                    Arg::with_name("output_format")
+                        .short("O")
+                        .long("output-format")
+                        .help("Format of the -b file. CRAM is compressed against the genomes in the graph, which are written to <file>.fa. (Default: bam)")
+                        .possible_values(&["sam", "bam", "cram"])
+                        .takes_value(true),
                 ).
                 arg(
-                    Arg::with_name("deconvolve")
-                        .short("D")
-                        .help("Deconvolve stuff (Default: off)")
-                        .hidden(true)
+                    Arg::with_name("write_unmapped")
+                        .long("write-unmapped")
+                        .help("With -a, write reads that don't align as unmapped records with their reason in an XU tag. (Default: leave them out)")
+                        .requires("align"),
                 ).
                 arg(
-                    Arg::with_name("penalty")
-                        .short("p")
-                        .help("Short read parameters. (Default: Long read params.)")
+                    Arg::with_name("rg")
+                        .long("rg")
+                        .help("Read group header line such as '@RG\\tID:foo\\tSM:bar'. Every record gets its ID as an RG tag. (Default: no read group)")
                         .takes_value(true)
-                        .hidden(true)
+                        .validator(|v| align::read_group_fields(&v).map(|_| ())),
                 ).
                 arg(
-                    Arg::with_name("samp_freq")
-                        .short("f")
-                        .help("Graph sampling frequency for strain detect. (Default: 30)")
-                        .takes_value(true)
+                    Arg::with_name("sort")
+                        .long("sort")
+                        .help("Sort the -b file by coordinate. (Default: records in the order reads finish)")
                 ).
                 arg(
-                    Arg::with_name("minimizer_weighting")
-                        .short("r")
-                        .help("marbl minimizer weighting file for k = 16. (Default: none)")
+                    Arg::with_name("sort_memory")
+                        .long("sort-memory")
+                        .help("Megabytes of records held in memory while sorting before spilling them to temporary files. (Default: 768)")
+                        .value_name("INT")
                         .takes_value(true)
+                        .requires("sort"),
                 ).
                 arg(
-                    Arg::with_name("h")
-                        .short("h")
-                        .help("h value (testing).")
+                    Arg::with_name("index")
+                        .long("index")
+                        .help("Index the sorted -b file. CRAM files get a .crai index for either type. (Default: no index)")
+                        .possible_values(&["bai", "csi"])
                         .takes_value(true)
+                        .requires("sort"),
+                ).
+                arg(
+                    Arg::with_name("aligner")
+                        .long("aligner")
+                        .help("Base level aligner for -a. block is adaptive banded alignment, wfa is heuristic (adaptive) gap-affine wavefront alignment. (Default: block)")
+                        .possible_values(&["block", "wfa"])
+                        .takes_value(true),
+                ).
+                arg(
+                    Arg::with_name("gaf")
+                        .short("g")
+                        .long("gaf")
+                        .help("Align reads to the graph at the base level and output the alignments in GAF format to this file. (Default: no graph alignment)")
+                        .takes_value(true),
+                ).
+                arg(
+                    Arg::with_name("paf")
+                        .long("paf")
+                        .help("Output the position of each reported chain on its genome in PAF format to this file, without base level alignment. Much faster than -a. (Default: no PAF output)")
+                        .takes_value(true),
+                ).
+                arg(
+                    Arg::with_name("recomb")
+                        .short("R")
+                        .long("recomb")
+                        .help("Infer colour switching paths for each chain, allowing reads to span recombination breakpoints, and output the segments and breakpoints to this file. (Default: no output)")
+                        .takes_value(true),
+                ).
+                arg(
+                    Arg::with_name("surject")
+                        .short("j")
+                        .long("surject")
+                        .help("With -g and -a, write each BAM record against the genome that carries most of the read's graph alignment. (Default: use the best chain colour)")
+                ).
+                arg(
+                    Arg::with_name("deconvolve")
+                        .short("D")
+                        .help("Deconvolve stuff (Default: off)")
                         .hidden(true)
                 ).
                 arg(
-                    Arg::with_name("w")
-                        .short("w")
-                        .help("w value (testing).")
+                    Arg::with_name("samp_freq")
+                        .short("f")
+                        .help("Graph sampling frequency for strain detect. (Default: 30)")
                         .takes_value(true)
-                        .hidden(true)
                 )
+                .args(&param_args())
         )
         .get_matches();
 
     let generate;
+    let paint;
     let matches_subc;
     if let Some(matches) = matches.subcommand_matches("generate") {
         generate = true;
+        paint = false;
+        matches_subc = matches;
+    } else if let Some(matches) = matches.subcommand_matches("paint") {
+        generate = false;
+        paint = true;
         matches_subc = matches;
     } else {
         matches_subc = matches.subcommand_matches("map").unwrap();
         generate = false;
+        paint = false;
     }
 
     let circular;
@@ -196,57 +346,90 @@ fn main() {
         circular = false;
     }
 
-    let align;
-    if matches_subc.is_present("align") {
-        align = true;
-    } else {
-        align = false;
-    }
-
-    let k = 16;
-    let s = 8;
-    let t = (k - s + 2) / 2 as usize;
-    //    let samp_freq = 30;
-    let samp_freq = matches_subc
-        .value_of("samp_freq")
-        .unwrap_or("30")
-        .parse::<usize>()
-        .unwrap();
-    //use syncmers if not using minimizers
-
-    let use_minimizers;
-    if matches_subc.is_present("syncmer") {
-        use_minimizers = false;
-    } else {
-        use_minimizers = true;
-    }
-
     let chain_heuristic;
     if matches_subc.is_present("chain_heuristic") {
         chain_heuristic = false;
     } else {
         chain_heuristic = true;
     }
-    let h = matches_subc
-        .value_of("h")
-        .unwrap_or("50")
-        .parse::<usize>()
-        .unwrap();
-    let w = matches_subc
-        .value_of("w")
-        .unwrap_or("16")
-        .parse::<usize>()
-        .unwrap();
-
-    let minimizer_weight_file = matches_subc.value_of("minimizer_weighting");
-    let frequent_kmers;
-    if let Some(file_str) = minimizer_weight_file {
-        frequent_kmers = seeding_methods_bit::read_minimizer_count_file(file_str);
-    } else {
-        frequent_kmers = FxHashMap::default();
+    let mut params = get_params(matches_subc);
+    //Seeding is only set when generating; map and paint use the graph's
+    if generate {
+        if let Some(v) = matches_subc.value_of("w") {
+            params.w = v.parse().unwrap();
+        }
+        if matches_subc.is_present("hpc") {
+            params.hpc = true;
+        }
     }
+    println!("Parameters: {:?}", params);
 
     if generate {
+        //    let samp_freq = 30;
+        let samp_freq = matches_subc
+            .value_of("samp_freq")
+            .unwrap_or("30")
+            .parse::<usize>()
+            .unwrap();
+        let k = 16;
+        let w = params.w;
+        let s = matches_subc
+            .value_of("syncmer_s")
+            .unwrap_or("8")
+            .parse::<usize>()
+            .unwrap();
+        let t;
+        if let Some(t_str) = matches_subc.value_of("syncmer_t") {
+            t = t_str.parse::<usize>().unwrap();
+        } else {
+            t = (k - s + 2) / 2 as usize;
+        }
+        let strobe_w_min = matches_subc
+            .value_of("strobe_w_min")
+            .unwrap_or("8")
+            .parse::<usize>()
+            .unwrap();
+        let strobe_w_max = matches_subc
+            .value_of("strobe_w_max")
+            .unwrap_or("40")
+            .parse::<usize>()
+            .unwrap();
+        if strobe_w_min > strobe_w_max {
+            clap::Error::with_description(
+                &format!(
+                    "--strobe-w-min {} is larger than --strobe-w-max {}",
+                    strobe_w_min, strobe_w_max
+                ),
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+        //use syncmers or strobemers if not using minimizers
+        let method;
+        if matches_subc.is_present("strobemer") {
+            method = SeedMethod::Strobemer;
+        } else if !matches_subc.is_present("syncmer") {
+            method = SeedMethod::Minimizer;
+        } else if matches_subc.is_present("closed") {
+            method = SeedMethod::ClosedSyncmer;
+        } else {
+            method = SeedMethod::OpenSyncmer;
+        }
+        let seed_params = SeedParams {
+            method: method,
+            k: k,
+            w: w,
+            s: s,
+            t: t,
+            strobe_w_min: strobe_w_min,
+            strobe_w_max: strobe_w_max,
+            //Presets for noisy reads turn on hpc, which strobemers don't support
+            hpc: params.hpc && method != SeedMethod::Strobemer,
+            soft_mask: matches_subc.is_present("soft_mask"),
+            seed_hash: constants::SEED_HASH.to_string(),
+        };
+        println!("Seeding parameters: {:?}", seed_params);
+
         let fraction_mask = matches_subc.value_of("mask").unwrap_or("0.0002");
         let fraction_mask_f64: f64 = fraction_mask.parse().unwrap();
 
@@ -255,6 +438,9 @@ fn main() {
         let mut good_chroms = vec![];
         let mut chrom_names = vec![];
         let mut good_chrom_names = vec![];
+        let mut chrom_gaps = vec![];
+        let mut chrom_masks = vec![];
+        let mut good_chrom_gaps = vec![];
 
         for i in 0..ref_genomes.len() {
             let reader = fasta::Reader::from_file(&ref_genomes[i]);
@@ -275,46 +461,53 @@ fn main() {
                 chrom_names.push(rec.id().to_string());
                 let chrom = DnaString::from_acgt_bytes(rec.seq());
                 chroms.push((chrom, true));
+                chrom_gaps.push(seeding_methods_bit::get_masked_intervals(rec.seq(), false));
+                chrom_masks.push(seeding_methods_bit::get_masked_intervals(
+                    rec.seq(),
+                    seed_params.soft_mask,
+                ));
             }
         }
 
         good_chroms.push((chroms[0].0.clone(), true));
         good_chrom_names.push(chrom_names[0].clone());
+        good_chrom_gaps.push(chrom_gaps[0].clone());
+
+        //Repetitive k-mers are counted over every genome unless a weighting file is given
+        let now = Instant::now();
+        let minimizer_weight_file = matches_subc.value_of("minimizer_weighting");
+        let frequent_kmers;
+        if let Some(file_str) = minimizer_weight_file {
+            frequent_kmers = seeding_methods_bit::read_minimizer_count_file(file_str);
+        } else if seed_params.method == SeedMethod::Minimizer {
+            frequent_kmers = seeding_methods_bit::get_frequent_kmers(&chroms, &seed_params);
+        } else {
+            frequent_kmers = FxHashMap::default();
+        }
 
         let mut seeds1;
-        let seed_p1;
         let dont_use_kmers = seeding_methods_bit::get_masked_kmers(
-            &chroms[0].0,
-            w,
-            k,
-            s,
-            t,
+            &chroms,
+            &chrom_masks,
+            &seed_params,
             fraction_mask_f64,
-            use_minimizers,
             &frequent_kmers,
         );
-        if use_minimizers {
-            seed_p1 = seeding_methods_bit::minimizer_seeds(
-                &chroms[0].0,
-                w,
-                k,
-                samp_freq,
-                &dont_use_kmers,
-                &frequent_kmers,
-                true,
-            );
-        } else {
-            seed_p1 = seeding_methods_bit::open_sync_seeds(
-                &chroms[0].0,
-                k,
-                t,
-                s,
-                samp_freq,
-                &dont_use_kmers,
-                &frequent_kmers,
-                true,
-            );
-        }
+        println!(
+            "Down-weighted {} k-mers and masked {} k-mers in {} seconds.",
+            frequent_kmers.len(),
+            dont_use_kmers.len(),
+            now.elapsed().as_secs_f32()
+        );
+        let seed_p1 = seeding_methods_bit::seed_sequence(
+            &chroms[0].0,
+            &seed_params,
+            samp_freq,
+            &dont_use_kmers,
+            &frequent_kmers,
+            &chrom_masks[0],
+            true,
+        );
         seeds1 = seed_p1.0;
         let p1 = seed_p1.1;
 
@@ -328,36 +521,30 @@ fn main() {
         let mut mean_score = 0.0;
         graph_utils::top_sort(&mut seeds1);
 
+        let mut genome_paths = vec![];
+        genome_paths.push(
+            p1.iter()
+                .enumerate()
+                .map(|(i, pos)| (i as u32, *pos as usize))
+                .collect::<Vec<(u32, usize)>>(),
+        );
+
         for i in 1..chroms.len() {
             let old_graph_len = seeds1.len();
             let genome_string = &chroms[i].0;
 
             println!("-----------------Iteration {}-------------------", i);
-            let mut seeds2;
+            let seeds2;
             let now = Instant::now();
-            let s2;
-            if use_minimizers {
-                s2 = seeding_methods_bit::minimizer_seeds(
-                    genome_string,
-                    w,
-                    k,
-                    1,
-                    &dont_use_kmers,
-                    &frequent_kmers,
-                    false,
-                );
-            } else {
-                s2 = seeding_methods_bit::open_sync_seeds(
-                    genome_string,
-                    k,
-                    t,
-                    s,
-                    1,
-                    &dont_use_kmers,
-                    &frequent_kmers,
-                    false,
-                );
-            }
+            let s2 = seeding_methods_bit::seed_sequence(
+                genome_string,
+                &seed_params,
+                1,
+                &dont_use_kmers,
+                &frequent_kmers,
+                &chrom_masks[i],
+                false,
+            );
             seeds2 = s2.0;
             println!(
                 "Generating sketch (minimizers) time: {}",
@@ -375,40 +562,25 @@ fn main() {
 
             let mut hash_vec: Vec<(&Kmer16, &usize)> = kmer_count_dict.iter().collect();
             hash_vec.sort_by(|a, b| b.1.cmp(a.1));
-            let qlen = seeds2.len();
 
             //            let (best_anchors, aln_score, forward_strand) = chain::chain_seeds(
             let anc_score_strand_vec = chain::chain_seeds(
-                &mut seeds1,
-                &mut seeds2,
+                &seeds1,
+                &seeds2,
                 &ref_hash_map,
                 &q_hash_map,
-                h,
+                &params,
                 chain_heuristic,
                 false,
                 &dont_use_kmers,
                 circular,
+                seed_params.method,
             );
 
             let (best_anchors, aln_score, forward_strand) = anc_score_strand_vec
                 .into_iter()
                 .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
                 .unwrap();
-            //Need to reverse the read strand so that it is "forward". Bad mutability
-            //design pattern here will change TODO
-            //Only need this for circula because circular does chaining for both strands,
-            //hence mutates the state back to normal. Needs to be reversed if
-            //reverse is the best strand. Non-circular already reverses during the chaining.
-            let order_val_last = seeds2[qlen - 1].order_val;
-            if forward_strand == false && circular {
-                for node in seeds2.iter_mut() {
-                    node.order = qlen as u32 - node.order - 1;
-                    //            for child_id in node.child_nodes.iter_mut(){
-                    //                *child_id = q_len as u32 - *child_id - 1;
-                    //            }
-                }
-            }
-
             if !forward_strand {
                 chroms[i].1 = false;
             }
@@ -427,12 +599,13 @@ fn main() {
             }
             good_chroms.push((chroms[i].0.clone(), forward_strand));
             good_chrom_names.push(chrom_names[i].clone());
+            good_chrom_gaps.push(chrom_gaps[i].clone());
 
             mean_score = (mean_score * (i - 1) as f64 + aln_score) / (i as f64);
             aln_score_array.push(aln_score);
 
             let now = Instant::now();
-            graph_utils::add_align_to_graph(
+            let genome_path = graph_utils::add_align_to_graph(
                 &mut seeds1,
                 seeds2,
                 best_anchors,
@@ -440,6 +613,7 @@ fn main() {
                 samp_freq,
                 circular,
             );
+            genome_paths.push(genome_path);
             println!(
                 "Generating graph from alignment time: {}",
                 now.elapsed().as_secs_f32()
@@ -456,6 +630,15 @@ fn main() {
             println!("Top sort time: {}.", now.elapsed().as_secs_f32());
         }
 
+        let now = Instant::now();
+        let seq_graph = graph_utils::label_edges(&seeds1, &genome_paths, &good_chroms, &good_chrom_gaps, k);
+        println!("Edge labelling time: {}.", now.elapsed().as_secs_f32());
+        if matches_subc.is_present("no_genomes") {
+            for chrom in good_chroms.iter_mut() {
+                chrom.0 = DnaString::new();
+            }
+        }
+
         let concat_graph = graph_utils::concat_graph(&seeds1[0], &seeds1);
         let mut file = File::create("simplified_mini_graph.csv").unwrap();
 
@@ -502,7 +685,15 @@ fn main() {
         let mut file_bin = BufWriter::new(File::create(serial_bin_name).unwrap());
         bincode::serialize_into(
             &mut file_bin,
-            &(&seeds1, &good_chroms, &good_chrom_names, &dont_use_kmers),
+            &(
+                &seeds1,
+                &good_chroms,
+                &good_chrom_names,
+                &dont_use_kmers,
+                &seq_graph,
+                &seed_params,
+                &frequent_kmers,
+            ),
         )
         .unwrap();
         println!(
@@ -510,6 +701,9 @@ fn main() {
             now.elapsed().as_secs_f32()
         );
 
+        let mut file_gfa = BufWriter::new(File::create(format!("{}.gfa", serial_name)).unwrap());
+        graph_utils::write_gfa(&seeds1, &seq_graph, &mut file_gfa);
+
         let mut file = File::create("full_mini_graph.csv").unwrap();
         for node in seeds1.iter() {
             //            println!("{:?},{}", node.kmer, node.kmer.to_string());
@@ -521,6 +715,146 @@ fn main() {
                 write!(&mut file, "{}", towrite).unwrap();
             }
         }
+    } else if paint {
+        let ref_graph_file = matches_subc.value_of("reference_graph").unwrap();
+        let bed_name = matches_subc.value_of("output").unwrap_or("paint.bed");
+        let ref_graph_reader = BufReader::new(File::open(ref_graph_file).unwrap());
+        let (mut ref_graph, chroms, chrom_names, dont_use_kmers, _seq_graph, seed_params, frequent_kmers): (
+            Vec<KmerNode>,
+            Vec<(DnaString, bool)>,
+            Vec<String>,
+            FxHashSet<Kmer16>,
+            SeqGraph,
+            SeedParams,
+            FxHashMap<Kmer16, usize>,
+        ) = bincode::deserialize_from(ref_graph_reader).unwrap();
+        seeding_methods_bit::check_seed_hash(&seed_params);
+        let k = seed_params.k;
+        graph_utils::top_sort(&mut ref_graph);
+        let ref_hash_map = chain::get_kmer_dict(&ref_graph);
+        let mut bed_file = BufWriter::new(File::create(bed_name).unwrap());
+
+        let assemblies: Vec<&str> = matches_subc.values_of("assemblies").unwrap().collect();
+        for assembly in assemblies {
+            let reader = fasta::Reader::from_file(assembly);
+            for record in reader.unwrap().records() {
+                let rec = record.unwrap();
+                println!("---------------Contig: {}---------------", rec.id());
+                let contig = DnaString::from_acgt_bytes(rec.seq());
+                let now = Instant::now();
+                let s2 = seeding_methods_bit::seed_sequence(
+                    &contig,
+                    &seed_params,
+                    1,
+                    &dont_use_kmers,
+                    &frequent_kmers,
+                    &seeding_methods_bit::get_masked_intervals(rec.seq(), seed_params.soft_mask),
+                    false,
+                );
+                let (contig_seeds, positions) = s2;
+                let contig_len = rec.seq().len();
+                if contig_seeds.is_empty() {
+                    println!("No seeds found for contig, unpainted");
+                    write_unpainted(&mut bed_file, rec.id(), 0, contig_len);
+                    continue;
+                }
+                let q_hash_map = chain::get_kmer_dict(&contig_seeds);
+                let anc_score_strand_vec = chain::chain_seeds(
+                    &ref_graph,
+                    &contig_seeds,
+                    &ref_hash_map,
+                    &q_hash_map,
+                    &params,
+                    chain_heuristic,
+                    false,
+                    &dont_use_kmers,
+                    circular,
+                    seed_params.method,
+                );
+                let best_chain = anc_score_strand_vec
+                    .into_iter()
+                    .max_by(|x, y| x.1.total_cmp(&y.1));
+                let (best_anchors, aln_score, forward_strand) = match best_chain {
+                    Some(chain) if !chain.0.is_empty() => chain,
+                    _ => {
+                        println!("No chain found for contig, unpainted");
+                        write_unpainted(&mut bed_file, rec.id(), 0, contig_len);
+                        continue;
+                    }
+                };
+                println!(
+                    "Chaining time and aln_score and strand: {},{},{}",
+                    now.elapsed().as_secs_f32(),
+                    aln_score,
+                    forward_strand
+                );
+
+                let segments = chain::get_colour_switching_path(
+                    &best_anchors,
+                    &ref_graph,
+                    &contig_seeds,
+                    chroms.len(),
+                    &params,
+                );
+                let strand_char;
+                if forward_strand {
+                    strand_char = '+';
+                } else {
+                    strand_char = '-';
+                }
+                //Only the best chain is painted, so the contig outside it is
+                //reported as unpainted
+                let mut painted_start = contig_len;
+                let mut painted_end = 0;
+                for segment in segments.iter() {
+                    painted_start = usize::min(painted_start, segment.qstart);
+                    painted_end = usize::max(painted_end, segment.qend);
+                }
+                if painted_start >= painted_end {
+                    painted_start = contig_len;
+                    painted_end = contig_len;
+                }
+                write_unpainted(&mut bed_file, rec.id(), 0, painted_start);
+                for segment in segments.iter() {
+                    //Mash-like identity from the fraction of the contig's seeds
+                    //in the segment that hit the segment's genome
+                    let num_seeds = positions.partition_point(|x| *x as usize + k <= segment.qend)
+                        - positions.partition_point(|x| (*x as usize) < segment.qstart);
+                    let identity = f64::min(segment.num_supporting as f64 / num_seeds as f64, 1.)
+                        .powf(1. / k as f64);
+
+                    let mut consistent_names = vec![];
+                    if segment.consistent_colors == 0 {
+                        consistent_names.push("*".to_string());
+                    } else {
+                        for bit in align::get_nonzero_bits_fast(segment.consistent_colors) {
+                            consistent_names.push(chrom_names[chroms.len() - bit - 1].clone());
+                        }
+                    }
+                    let breakpoint_string;
+                    if let Some((left, right)) = segment.breakpoint {
+                        breakpoint_string = format!("{}", (left + right) / 2);
+                    } else {
+                        breakpoint_string = "*".to_string();
+                    }
+                    writeln!(
+                        &mut bed_file,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}",
+                        rec.id(),
+                        segment.qstart,
+                        segment.qend,
+                        chrom_names[chroms.len() - align::get_first_nonzero_bit(segment.color) - 1],
+                        (identity * 1000.) as u32,
+                        strand_char,
+                        consistent_names.join(","),
+                        identity,
+                        breakpoint_string
+                    )
+                    .unwrap();
+                }
+                write_unpainted(&mut bed_file, rec.id(), painted_end, contig_len);
+            }
+        }
     } else {
         let num_t_str = matches_subc.value_of("threads").unwrap_or("10");
         let num_t = match num_t_str.parse::<usize>() {
@@ -532,30 +866,112 @@ fn main() {
             .build_global()
             .unwrap();
 
+        let align = matches_subc.is_present("align");
         let dont_output_stuff = matches_subc.is_present("dont_output_stuff");
-        let short_reads = matches_subc.is_present("short_reads");
         let ref_graph_file = matches_subc.value_of("reference_graph").unwrap();
-        let bam_name = matches_subc.value_of("bam_name").unwrap_or("output.bam");
-
-        let ref_graph_f = File::open(ref_graph_file).unwrap();
-        let ref_graph_reader = BufReader::new(ref_graph_f);
-
-        let (mut ref_graph, chroms, chrom_names, dont_use_kmers): (
-            Vec<KmerNode>,
-            Vec<(DnaString, bool)>,
-            Vec<String>,
-            FxHashSet<Kmer16>,
-        ) = bincode::deserialize_from(ref_graph_reader).unwrap();
+        let output_format_name = matches_subc.value_of("output_format").unwrap_or("bam");
+        let output_format = bam_output::format_from_name(output_format_name).unwrap();
+        let default_bam_name = format!("output.{}", output_format_name);
+        let bam_name = matches_subc.value_of("bam_name").unwrap_or(&default_bam_name);
+        let sort = matches_subc.is_present("sort");
+        let write_unmapped = matches_subc.is_present("write_unmapped");
+        let sort_memory;
+        if let Some(v) = matches_subc.value_of("sort_memory") {
+            sort_memory = v.parse::<usize>().unwrap() * 1_000_000;
+        } else {
+            sort_memory = 768_000_000;
+        }
+        let index_type;
+        if matches_subc.value_of("index") == Some("csi") {
+            index_type = Some(index::Type::Csi(14));
+        } else if matches_subc.value_of("index") == Some("bai") {
+            index_type = Some(index::Type::Bai);
+        } else {
+            index_type = None;
+        }
+        let mut read_group = vec![];
+        if let Some(v) = matches_subc.value_of("rg") {
+            read_group = align::read_group_fields(v).unwrap();
+        }
+        let read_group_id = read_group
+            .iter()
+            .find(|x| x.0 == "ID")
+            .map(|x| x.1.clone());
+        if index_type.is_some() && output_format_name == "sam" {
+            panic!("SAM output can't be indexed; use -O bam or -O cram");
+        }
+        let gaf_name = matches_subc.value_of("gaf");
+        let gaf = gaf_name.is_some();
+        let paf_name = matches_subc.value_of("paf");
+        let paf = paf_name.is_some();
+        let surject = matches_subc.is_present("surject");
+        let recomb_name = matches_subc.value_of("recomb");
+
+        let mut mapper = mapper::Mapper::from_file(ref_graph_file, params)
+            .unwrap_or_else(|e| panic!("Couldn't load graph {}: {}", ref_graph_file, e));
+        if matches_subc.is_present("preset")
+            && (mapper.params.w != mapper.seed_params.w || mapper.params.hpc != mapper.seed_params.hpc)
+        {
+            println!("The preset's seeding parameters differ from the graph's; reads are seeded with the graph's.");
+        }
+        mapper.circular = circular;
+        mapper.chain_heuristic = chain_heuristic;
+        mapper.align = align;
+        if let Some(v) = matches_subc.value_of("aligner") {
+            mapper.aligner = aligner::aligner_from_name(v).unwrap();
+        }
+        mapper.graph_align = gaf;
+        mapper.paf = paf;
+        mapper.surject = surject;
+        mapper.recomb = recomb_name.is_some();
+        let chroms = &mapper.chroms;
+        let chrom_names = &mapper.chrom_names;
 
-        let order_to_id = graph_utils::top_sort(&mut ref_graph);
         let reads_file = matches_subc.value_of("reads").unwrap();
         let reader = fastq::Reader::from_file(reads_file);
 
-        let ref_hash_map = chain::get_kmer_dict(&ref_graph);
         let mut anchor_file = BufWriter::new(File::create("read_anchor_hits.txt").unwrap());
         let mut best_genomes_file = File::create("best_genome_reads.txt").unwrap();
-        let (headerview, mut writer) =
-            align::write_bam_header(&chroms, &chrom_names, bam_name.to_string());
+        let command_line = std::env::args().collect::<Vec<String>>().join(" ");
+        let mut colours = vec![];
+        for i in 0..chroms.len() {
+            colours.push(format!("{}:{}", i, chrom_names[chroms.len() - i - 1]));
+        }
+        let comments = vec![
+            format!("graph: {}", ref_graph_file),
+            format!("graph seeding: {:?}", mapper.seed_params),
+            format!("colour bits: {}", colours.join(" ")),
+        ];
+        let mut bam_writer = bam_output::BamOutput::new(
+            bam_name,
+            output_format,
+            align::bam_header(
+                chroms,
+                &mapper.chrom_gaps,
+                chrom_names,
+                sort,
+                &read_group,
+                &command_line,
+                &comments,
+            ),
+            chroms,
+            &mapper.chrom_gaps,
+            chrom_names,
+            sort,
+            sort_memory,
+        );
+        let mut gaf_file = None;
+        if let Some(name) = gaf_name {
+            gaf_file = Some(BufWriter::new(File::create(name).unwrap()));
+        }
+        let mut paf_file = None;
+        if let Some(name) = paf_name {
+            paf_file = Some(BufWriter::new(File::create(name).unwrap()));
+        }
+        let mut recomb_file = None;
+        if let Some(name) = recomb_name {
+            recomb_file = Some(BufWriter::new(File::create(name).unwrap()));
+        }
 
         //        let graph_simp_time = Instant::now();
         //        let simplified_graph = graph_utils::concat_graph(&ref_graph[0], &ref_graph);
@@ -564,12 +980,16 @@ fn main() {
         let start_align = Instant::now();
         let mut record_container = vec![];
         let mut bam_info_container: Mutex<Vec<_>> = Mutex::new(vec![]);
+        let mut unmapped_container: Mutex<Vec<_>> = Mutex::new(vec![]);
+        let mut gaf_info_container: Mutex<Vec<_>> = Mutex::new(vec![]);
+        let mut paf_info_container: Mutex<Vec<_>> = Mutex::new(vec![]);
+        let mut recomb_container: Mutex<Vec<String>> = Mutex::new(vec![]);
         let mut best_hit_for_read: Mutex<FxHashMap<_, _>> = Mutex::new(FxHashMap::default());
 
         let mut records = reader.unwrap().records().peekable();
-        //        let batch = num_t * 200;
-        let batch = usize::MAX;
-        let time_stuff = true;
+        //Map the reads in batches so only one batch of reads and its output is
+        //held at a time
+        let batch = num_t * 200;
         while let Some(Ok(record)) = records.next() {
             if record_container.len() < batch {
                 record_container.push(record);
@@ -579,297 +999,370 @@ fn main() {
                     .collect::<Vec<usize>>()
                     .into_par_iter()
                     .for_each(|i| {
-                        let now = Instant::now();
-                        let total_time = Instant::now();
                         let rec = &record_container[i];
-                        let read = DnaString::from_acgt_bytes(rec.seq());
-                        let quals = rec.qual().to_vec();
                         let read_id = rec.id().to_string();
-
-                        let mut best_colors_both_strands = vec![];
-                        let mut best_anchors_both_strands = vec![];
-                        let mut chain_numbers = vec![];
-                        let mut strand_anchor_vec = vec![];
-
-                        if time_stuff {
-                            println!("Preprocess time: {}", now.elapsed().as_secs_f32());
-                        }
-
-                        println!("---------------Read: {}---------------", read_id);
-                        let now = Instant::now();
-                        let mut read_seeds;
-                        let s2;
-                        if use_minimizers {
-                            s2 = seeding_methods_bit::minimizer_seeds(
-                                &read,
-                                w,
-                                k,
-                                1,
-                                &FxHashSet::default(),
-                                &frequent_kmers,
-                                false,
-                            );
-                        } else {
-                            s2 = seeding_methods_bit::open_sync_seeds(
-                                &read,
-                                k,
-                                t,
-                                s,
-                                1,
-                                &FxHashSet::default(),
-                                &frequent_kmers,
-                                false,
-                            );
-                        }
-                        if time_stuff {
-                            println!("Seeding time: {}", now.elapsed().as_secs_f32());
-                        }
-
-                        read_seeds = s2.0;
-                        let qlen = read_seeds.len();
-                        let q_hash_map = chain::get_kmer_dict(&read_seeds);
-                        let now = Instant::now();
-                        let mut anc_score_strand_vec = chain::chain_seeds(
-                            &ref_graph,
-                            &mut read_seeds,
-                            &ref_hash_map,
-                            &q_hash_map,
-                            h,
-                            chain_heuristic,
-                            true,
-                            //                            &dont_use_kmers,
-                            &FxHashSet::default(),
-                            circular,
-                        );
-
-                        if time_stuff {
-                            println!("Chaining time: {}", now.elapsed().as_secs_f32());
+                        let mappings = mapper.map_read(&read_id, rec.seq(), rec.qual()).mappings;
+
+                        if write_unmapped {
+                            if let Some(reason) = align::unmapped_reason(&mappings) {
+                                let unmapped_rec = align::get_unmapped_bam_record(
+                                    &read_id,
+                                    rec.seq(),
+                                    rec.qual(),
+                                    reason,
+                                    read_group_id.as_deref(),
+                                );
+                                unmapped_container.lock().unwrap().push(unmapped_rec);
+                            }
                         }
 
-                        for (k, (best_anchors, _aln_score, read_strand)) in
-                            anc_score_strand_vec.iter().enumerate()
-                        {
-                            if *read_strand == false {
-                                for node in read_seeds.iter_mut() {
-                                    node.order = qlen as u32 - node.order - 1;
-                                    //            for child_id in node.child_nodes.iter_mut(){
-                                    //                *child_id = q_len as u32 - *child_id - 1;
-                                    //            }
-                                }
+                        for mapping in mappings.iter() {
+                            if mapping.segments.is_empty() {
+                                continue;
                             }
-                            let now = Instant::now();
-
-//                            let (best_colors, best_list_anchors) = chain::get_best_path_from_chain2(
-                            let (best_colors, best_list_anchors) = chain::get_best_path_from_chain_rewrite(
-                                best_anchors,
-                                &ref_graph,
-                                &order_to_id,
-                                &read_seeds,
-                                read.len(),
-                                false,
-                            );
-
-                            if time_stuff {
-                                println!("Path collection time: {}", now.elapsed().as_secs_f32());
+                            let strand_char;
+                            if mapping.strand {
+                                strand_char = '+';
+                            } else {
+                                strand_char = '-';
                             }
-
-                            //TODO don't want to do clone every anchor list.
-                            for i in 0..best_colors.len() {
-                                best_colors_both_strands.push(best_colors[i]);
-                                best_anchors_both_strands.push(best_list_anchors[i].clone());
-                                strand_anchor_vec.push(*read_strand);
-                                chain_numbers.push(k);
-                            }
-                        }
-
-                        if align {
-                            let mut do_align = true;
-                            let do_base_chain = true;
-                            if best_anchors_both_strands.len() == 0 {
-                                if read.len() > constants::READ_LENGTH_SUPER_CHAIN_CUTOFF
-                                    && do_base_chain
-                                {
-                                    println!("No good alignment found; primary-ref-chaining");
-                                    let now = Instant::now();
-                                    //Do base chaining if no good alignment is found
-                                    let mut base_anc_score_strand_vec =
-                                        coord_chain::get_base_chains(
-                                            &ref_graph,
-                                            &read_seeds,
-                                            &ref_hash_map,
-                                            &q_hash_map,
-                                            h,
-                                            &FxHashSet::default(),
-                                            read.len(),
-                                        );
-
-                                    if time_stuff {
-                                        println!(
-                                            "primary-ref chaining time: {}",
-                                            now.elapsed().as_secs_f32()
-                                        );
-                                    }
-
-                                    for (k, (best_anchors, _aln_score, read_strand)) in
-                                        base_anc_score_strand_vec.iter().enumerate()
-                                    {
-                                        if *read_strand == false {
-                                            for node in read_seeds.iter_mut() {
-                                                node.order = qlen as u32 - node.order - 1;
-                                                //            for child_id in node.child_nodes.iter_mut(){
-                                                //                *child_id = q_len as u32 - *child_id - 1;
-                                                //            }
-                                            }
-                                        }
-                                        let now = Instant::now();
-
-                                        let (best_colors, best_list_anchors) =
-                                            chain::get_best_path_from_chain_rewrite(
-                                                best_anchors,
-                                                &ref_graph,
-                                                &order_to_id,
-                                                &read_seeds,
-                                                read.len(),
-                                                true,
-                                            );
-
-                                        if time_stuff {
-                                            println!(
-                                                "Path collection time: {}",
-                                                now.elapsed().as_secs_f32()
-                                            );
-                                        }
-
-                                        //TODO don't want to do clone every anchor list.
-                                        for i in 0..best_colors.len() {
-                                            best_colors_both_strands.push(best_colors[i]);
-                                            best_anchors_both_strands
-                                                .push(best_list_anchors[i].clone());
-                                            chain_numbers.push(k);
-                                            strand_anchor_vec.push(*read_strand);
-                                        }
-
-                                        if best_anchors_both_strands.len() > 0 {
-                                            do_align = true;
-                                        } else {
-                                            do_align = false;
-                                        }
-                                    }
+                            let mut locked = recomb_container.lock().unwrap();
+                            for segment in mapping.segments.iter() {
+                                let mut consistent_names = vec![];
+                                if segment.consistent_colors == 0 {
+                                    consistent_names.push("*".to_string());
                                 } else {
-                                    println!("No good alignment found");
-                                    do_align = false;
-                                }
-                            }
-                            if do_align {
-                                let now = Instant::now();
-                                use std::cmp::min;
-                                //Print best anchors
-                                let mut best_indices = best_anchors_both_strands
-                                    .iter()
-                                    .enumerate()
-                                    .collect::<Vec<(_, _)>>();
-                                best_indices
-                                    .sort_by(|(_, a), (_, b)| b.1.partial_cmp(&a.1).unwrap());
-                                let best_indices: Vec<usize> =
-                                    best_indices.iter().map(|(index, _)| *index).collect();
-                                let top_n = 10;
-                                //                                writeln!(&mut best_genomes_file, ">{}", &read_id).unwrap();
-                                for _i in 0..min(top_n, best_indices.len()) {
-                                    let mut locked = best_hit_for_read.lock().unwrap();
-                                    let ith_color = best_colors_both_strands[best_indices[_i]];
-                                    let ith_score = best_anchors_both_strands[best_indices[_i]].1;
-                                    let ith_ref_chroms = align::get_nonzero_bits(ith_color);
-                                    for bit in ith_ref_chroms {
-                                        //                                        writeln!(
-                                        //                                            &mut best_genomes_file,
-                                        //                                            "{}\t{}",
-                                        //                                            &chrom_names[chroms.len() - bit - 1],
-                                        //                                            ith_score
-                                        //                                        )
-                                        //                                        .unwrap();
-                                        let vec = locked.entry(read_id.clone()).or_insert(vec![]);
-                                        vec.push((ith_score, &chrom_names[chroms.len() - bit - 1]))
+                                    for bit in align::get_nonzero_bits_fast(segment.consistent_colors) {
+                                        consistent_names.push(chrom_names[chroms.len() - bit - 1].clone());
                                     }
                                 }
-                                if time_stuff {
-                                    println!(
-                                        "Align preprocess time {}",
-                                        now.elapsed().as_secs_f32()
-                                    );
-                                }
-
-                                let map_indices;
-                                let map_all = true;
-                                if map_all {
-                                    map_indices = top_n;
+                                let breakpoint_string;
+                                if let Some((left, right)) = segment.breakpoint {
+                                    breakpoint_string = format!("{}\t{}-{}", (left + right) / 2, left, right);
                                 } else {
-                                    map_indices = 1;
+                                    breakpoint_string = format!("*\t*");
                                 }
+                                locked.push(format!(
+                                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}",
+                                    read_id,
+                                    mapping.chain,
+                                    strand_char,
+                                    segment.qstart,
+                                    segment.qend,
+                                    chrom_names[chroms.len() - align::get_first_nonzero_bit(segment.color) - 1],
+                                    consistent_names.join(","),
+                                    segment.end_anchor - segment.start_anchor + 1,
+                                    segment.support,
+                                    breakpoint_string
+                                ));
+                            }
+                        }
 
-                                let now = Instant::now();
-                                let mut used_chains = FxHashSet::default();
-                                for index in 0..usize::min(best_indices.len(), map_indices) {
-                                    let best_index = best_indices[index];
-                                    let best_score = best_anchors_both_strands[best_indices[0]].1;
-                                    let score = best_anchors_both_strands[best_index].1;
-                                    let chain_number = chain_numbers[best_index];
-                                    if (best_score < score + 50.
-                                        || (best_score - score) < 0.05 * (&best_anchors_both_strands[best_index].0.len() * 16) as f64)
-                                        && !used_chains.contains(&chain_number)
-                                    {
-                                        used_chains.insert(chain_number);
-                                        let anchors = &best_anchors_both_strands[best_index].0;
-                                        let color = &best_colors_both_strands[best_index];
-                                        let read_strand = strand_anchor_vec[best_index];
-
-                                        let bam_info = align::align_from_chain(
-                                            anchors,
-                                            &chroms,
-                                            *color,
-                                            &ref_graph,
-                                            &read_seeds,
-                                            &read,
-                                            read_strand,
-                                            &quals,
-                                            &chrom_names,
-                                            &read_id,
-                                        );
-                                        let mut locked = bam_info_container.lock().unwrap();
-                                        locked.push(bam_info);
-                                    }
+                        if align || gaf || paf {
+                            //Top 10 colour paths over all chains
+                            let mut best_paths = vec![];
+                            for mapping in mappings.iter() {
+                                for path in mapping.paths.iter() {
+                                    best_paths.push((path.0, path.2));
                                 }
-                                if time_stuff {
-                                    println!("Total align time {}", now.elapsed().as_secs_f32());
+                            }
+                            best_paths.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+                            let top_n = 10;
+                            for (ith_color, ith_score) in best_paths.into_iter().take(top_n) {
+                                let mut locked = best_hit_for_read.lock().unwrap();
+                                for bit in align::get_nonzero_bits(ith_color) {
+                                    let vec = locked.entry(read_id.clone()).or_insert(vec![]);
+                                    vec.push((ith_score, &chrom_names[chroms.len() - bit - 1]))
                                 }
                             }
                         }
-                        if time_stuff {
-                            println!(
-                                "Total time mapping read {} is {}",
-                                read_id,
-                                total_time.elapsed().as_secs_f32()
-                            );
+
+                        for mapping in mappings {
+                            if mapping.aligned_color.is_none() {
+                                continue;
+                            }
+                            if let Some(gaf_info) = mapping.gaf_info {
+                                let mut locked = gaf_info_container.lock().unwrap();
+                                locked.push(gaf_info);
+                            }
+                            if align {
+                                let mut locked = bam_info_container.lock().unwrap();
+                                locked.extend(mapping.bam_info);
+                            }
+                            if paf {
+                                let mut locked = paf_info_container.lock().unwrap();
+                                locked.extend(mapping.paf_info);
+                            }
                         }
                     });
                 for bam_info in bam_info_container.into_inner().unwrap() {
-                    if !bam_info.is_none() {
-                        let bam_rec = align::get_bam_record(bam_info.unwrap(), &headerview);
-                        writer.write(&bam_rec).unwrap();
+                    let bam_rec = align::get_bam_record(
+                        bam_info,
+                        bam_writer.headerview(),
+                        read_group_id.as_deref(),
+                    );
+                    bam_writer.write(bam_rec);
+                }
+                for unmapped_rec in unmapped_container.into_inner().unwrap() {
+                    bam_writer.write(unmapped_rec);
+                }
+                for gaf_info in gaf_info_container.into_inner().unwrap() {
+                    let gaf_writer = gaf_file.as_mut().unwrap();
+                    writeln!(gaf_writer, "{}", graph_align::gaf_string(&gaf_info)).unwrap();
+                }
+                for paf_info in paf_info_container.into_inner().unwrap() {
+                    let paf_writer = paf_file.as_mut().unwrap();
+                    writeln!(paf_writer, "{}", align::paf_string(&paf_info)).unwrap();
+                }
+                for line in recomb_container.into_inner().unwrap() {
+                    writeln!(recomb_file.as_mut().unwrap(), "{}", line).unwrap();
+                }
+                for (read_id, hits) in best_hit_for_read.into_inner().unwrap() {
+                    writeln!(&mut best_genomes_file, ">{}", &read_id).unwrap();
+                    for hit in hits {
+                        writeln!(&mut best_genomes_file, "{}\t{}", hit.1, hit.0).unwrap();
                     }
                 }
                 record_container = vec![];
                 bam_info_container = Mutex::new(vec![]);
+                unmapped_container = Mutex::new(vec![]);
+                recomb_container = Mutex::new(vec![]);
+                gaf_info_container = Mutex::new(vec![]);
+                paf_info_container = Mutex::new(vec![]);
+                best_hit_for_read = Mutex::new(FxHashMap::default());
             }
         }
-        for (read_id, hits) in best_hit_for_read.into_inner().unwrap() {
-            writeln!(&mut best_genomes_file, ">{}", &read_id).unwrap();
-            for hit in hits {
-                writeln!(&mut best_genomes_file, "{}\t{}", hit.1, hit.0).unwrap();
-            }
-        }
+        bam_writer.finish(index_type);
         println!(
             "Alignment took {} seconds",
             start_align.elapsed().as_secs_f32()
         );
     }
 }
+
+//A strobe offset must leave room for the first strobe, and the strobemer's
+//span, offset plus strobe length, must fit the u16 span of a seed.
+fn strobe_window(value: &str, strobe_len: usize) -> Result<(), String> {
+    let offset = match value.parse::<usize>() {
+        Ok(offset) => offset,
+        Err(_) => return Err(format!("{} is not a positive integer", value)),
+    };
+    if offset < strobe_len {
+        return Err(format!("strobe offsets must be at least k/2 = {}", strobe_len));
+    }
+    if offset + strobe_len > u16::MAX as usize {
+        return Err(format!(
+            "strobe offsets must be at most {} so spans fit in 16 bits",
+            u16::MAX as usize - strobe_len
+        ));
+    }
+    return Ok(());
+}
+
+//BED line for part of a contig that no chain covers
+fn write_unpainted<W: Write>(bed_file: &mut W, contig: &str, start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    println!("Unpainted {}-{}", start, end);
+    writeln!(bed_file, "{}\t{}\t{}\t*\t0\t.\t*\t0.0000\t*", contig, start, end).unwrap();
+}
+
+//Chaining, scoring and alignment options shared by every subcommand. Each one
+//overrides the value from -P.
+fn param_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("preset")
+            .short("P")
+            .long("preset")
+            .help("Set seeding, chaining and alignment parameters for a data type: hifi, ont, clr, sr (short reads) or asm (assemblies). (Default: long read parameters)")
+            .takes_value(true)
+            .possible_values(&constants::PRESETS),
+        Arg::with_name("h")
+            .short("h")
+            .long("band")
+            .help("Number of previous anchors looked at by the chaining heuristic. (Default: 50)")
+            .takes_value(true),
+        Arg::with_name("max_occ")
+            .long("max-occ")
+            .help("Don't anchor read k-mers with more than this many hits in the graph. (Default: 500)")
+            .takes_value(true),
+        Arg::with_name("colour_chaining")
+            .long("colour-chaining")
+            .help("How chaining reads treats colour: blind ignores it, penalise charges --colour-penalty for chaining anchors sharing no genome and strict doesn't allow it. (Default: blind)")
+            .takes_value(true)
+            .possible_values(&["blind", "penalise", "strict"]),
+        Arg::with_name("colour_penalty")
+            .long("colour-penalty")
+            .help("Penalty for chaining anchors sharing no genome with --colour-chaining penalise. (Default: 250)")
+            .takes_value(true),
+        Arg::with_name("anchor_score")
+            .long("anchor-score")
+            .help("Score of an anchor when chaining reads. (Default: 50)")
+            .takes_value(true),
+        Arg::with_name("contig_anchor_score")
+            .long("contig-anchor-score")
+            .help("Score of an anchor when chaining genomes and contigs. (Default: 100)")
+            .takes_value(true),
+        Arg::with_name("band_anchor_score")
+            .long("band-anchor-score")
+            .help("Score of an anchor for the chaining heuristic. (Default: 50)")
+            .takes_value(true),
+        Arg::with_name("coord_anchor_score")
+            .long("coord-anchor-score")
+            .help("Score of an anchor when chaining on the first genome's coordinates. (Default: 100)")
+            .takes_value(true),
+        Arg::with_name("min_chain_score")
+            .long("min-chain-score")
+            .help("Smallest score of a chain. (Default: 100)")
+            .takes_value(true),
+        Arg::with_name("secondary_ratio")
+            .long("secondary-ratio")
+            .help("Smallest score of other chains relative to the best chain. (Default: 0.9)")
+            .takes_value(true),
+        Arg::with_name("strand_ratio")
+            .long("strand-ratio")
+            .help("Chain only one strand if it has this many times more anchors than the other. (Default: 1.111)")
+            .takes_value(true),
+        Arg::with_name("path_anchor_score")
+            .long("path-anchor-score")
+            .help("Score of an anchor when finding the best colours for a chain. (Default: 50)")
+            .takes_value(true),
+        Arg::with_name("path_threshold")
+            .long("path-threshold")
+            .help("Smallest score per anchor of a colour path. (Default: 25)")
+            .takes_value(true),
+        Arg::with_name("switch_penalty")
+            .long("switch-penalty")
+            .help("Penalty for switching colour in colour switching paths. (Default: 250)")
+            .takes_value(true),
+        Arg::with_name("primary_chain_min_len")
+            .long("primary-chain-min-len")
+            .help("Smallest read length to chain on the first genome's coordinates when no chain is found. (Default: 3000)")
+            .takes_value(true),
+        Arg::with_name("match_score")
+            .long("match")
+            .help("Match score for base alignment. (Default: 1)")
+            .takes_value(true),
+        Arg::with_name("mismatch")
+            .long("mismatch")
+            .help("Mismatch penalty for base alignment. (Default: 2)")
+            .takes_value(true),
+        Arg::with_name("gap_open")
+            .long("gap-open")
+            .help("Penalty for the first base of a gap in base alignment. (Default: 2)")
+            .takes_value(true),
+        Arg::with_name("gap_extend")
+            .long("gap-extend")
+            .help("Penalty for every other base of a gap in base alignment. (Default: 1)")
+            .takes_value(true),
+        Arg::with_name("x_drop")
+            .long("x-drop")
+            .help("X-drop for base alignment. (Default: 50)")
+            .takes_value(true),
+        Arg::with_name("block_size")
+            .long("block-size")
+            .help("Block size for base alignment, a power of two. (Default: 512)")
+            .takes_value(true),
+        Arg::with_name("wfa_min_wavefront")
+            .long("wfa-min-wavefront")
+            .help("Wavefronts shorter than this aren't reduced with --aligner wfa. (Default: 10)")
+            .takes_value(true),
+        Arg::with_name("wfa_max_distance")
+            .long("wfa-max-distance")
+            .help("Drop wavefront diagonals this far behind the furthest one with --aligner wfa. (Default: 50)")
+            .takes_value(true),
+        Arg::with_name("max_graph_cells")
+            .long("max-graph-cells")
+            .help("Largest number of DP cells for aligning a read between two anchors with -g. (Default: 20000000)")
+            .takes_value(true),
+    ]
+}
+
+fn get_params(matches: &ArgMatches) -> Params {
+    let mut params;
+    if let Some(preset) = matches.value_of("preset") {
+        params = constants::preset_params(preset).unwrap();
+    } else {
+        params = constants::default_params();
+    }
+    if let Some(v) = matches.value_of("h") {
+        params.h = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("max_occ") {
+        params.max_occ = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("colour_chaining") {
+        if v == "penalise" {
+            params.colour_chaining = ColourChaining::Penalise;
+        } else if v == "strict" {
+            params.colour_chaining = ColourChaining::Strict;
+        } else {
+            params.colour_chaining = ColourChaining::Blind;
+        }
+    }
+    if let Some(v) = matches.value_of("colour_penalty") {
+        params.colour_penalty = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("anchor_score") {
+        params.read_chain_base_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("contig_anchor_score") {
+        params.contig_chain_base_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("band_anchor_score") {
+        params.banded_chaining_base_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("coord_anchor_score") {
+        params.coord_chain_base_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("min_chain_score") {
+        params.chain_cutoff_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("secondary_ratio") {
+        params.secondary_chain_cutoff_percent = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("strand_ratio") {
+        params.ambiguous_fraction = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("path_anchor_score") {
+        params.path_chain_base_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("path_threshold") {
+        params.path_threshold_fraction = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("switch_penalty") {
+        params.switch_penalty = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("primary_chain_min_len") {
+        params.read_length_super_chain_cutoff = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("match_score") {
+        params.match_score = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("mismatch") {
+        params.mismatch = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("gap_open") {
+        params.gap_open = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("gap_extend") {
+        params.gap_extend = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("x_drop") {
+        params.x_drop = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("block_size") {
+        params.block_size = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("wfa_min_wavefront") {
+        params.wfa_min_wavefront_length = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("wfa_max_distance") {
+        params.wfa_max_distance_threshold = v.parse().unwrap();
+    }
+    if let Some(v) = matches.value_of("max_graph_cells") {
+        params.graph_align_max_cells = v.parse().unwrap();
+    }
+    return params;
+}
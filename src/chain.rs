@@ -2,7 +2,7 @@ use crate::align;
 use crate::avl_tree::SearchTree;
 use crate::constants;
 use crate::data_structs::KmerNode;
-use crate::data_structs::{Anchors, Color};
+use crate::data_structs::{Anchors, Color, ColourChaining, ColourSegment, Params, SeedMethod};
 use debruijn::kmer::Kmer16;
 use debruijn::Kmer;
 use fxhash::FxHashMap;
@@ -55,7 +55,7 @@ pub fn position_max_f64_nth(slice: &[f64], n: usize) -> usize {
 }
 
 #[inline]
-fn heuristic_score(ref_order_dist: f64, query_order_dist: f64) -> f64 {
+fn heuristic_score(ref_order_dist: f64, query_order_dist: f64, base_score: f64) -> f64 {
     //    let dist_ref = -2.0 * f64::sqrt(ref_order_dist);
     //    let max_dist = f64::max(ref_order_dist,query_order_dist);
     //    let dist_ref = -1.0 * ref_order_dist;
@@ -69,37 +69,45 @@ fn heuristic_score(ref_order_dist: f64, query_order_dist: f64) -> f64 {
     //    let fuzzy_gap_cost = gap_cost.powi(2) / query_order_dist;
     //    let linear_cost = f64::sqrt(ref_order_dist) + query_order_dist;
     //    let score = constants::BANDED_CHAINING_BASE_SCORE - f64::min(gap_cost, fuzzy_gap_cost);
-    let score = constants::BANDED_CHAINING_BASE_SCORE - gap_cost;
+    let score = base_score - gap_cost;
     //    let score = constants::BANDED_CHAINING_BASE_SCORE - linear_cost;
     return score;
 }
 
+//Orders and order_vals of the query seeds as seen from one strand. On the
+//reverse strand both count from the end of the query.
+pub fn strand_orders(seeds_q: &Vec<KmerNode>, forward_strand: bool) -> (Vec<u32>, Vec<u32>) {
+    let q_len = seeds_q.len() as u32;
+    let order_val_last = seeds_q[seeds_q.len() - 1].order_val;
+    let mut orders = Vec::with_capacity(seeds_q.len());
+    let mut order_vals = Vec::with_capacity(seeds_q.len());
+    for node in seeds_q.iter() {
+        if forward_strand {
+            orders.push(node.order);
+            order_vals.push(node.order_val);
+        } else {
+            orders.push(q_len - node.order - 1);
+            order_vals.push(order_val_last - node.order_val);
+        }
+    }
+    return (orders, order_vals);
+}
+
 fn get_chains<'a>(
     seeds_ref: &'a Vec<KmerNode>,
-    seeds_q: &'a mut Vec<KmerNode>,
-    h: usize,
+    seeds_q: &'a Vec<KmerNode>,
+    params: &Params,
     chain_heuristic: bool,
     chain_reads: bool,
     circular: bool,
     forward_strand: bool,
     anchors: &mut Vec<(u32, u32)>,
+    seed_method: SeedMethod,
 ) -> Vec<(Anchors, f64)> {
-    let q_len = seeds_q.len();
-    let order_val_last = seeds_q[q_len - 1].order_val;
-
-    if forward_strand == false {
-        for node in seeds_q.iter_mut() {
-            node.order = q_len as u32 - node.order - 1;
-            node.order_val = order_val_last - node.order_val;
-            //            for child_id in node.child_nodes.iter_mut(){
-            //                *child_id = q_len as u32 - *child_id - 1;
-            //            }
-        }
-    }
-
     if anchors.len() == 0 {
         return vec![(vec![], 0.0)];
     }
+    let (q_orders, q_order_vals) = strand_orders(seeds_q, forward_strand);
 
     //dbg!(anchors[1],anchors[2],anchors[3]);
     //dbg!(alpha(1,2,&anchors,k),alpha(2,3,&anchors,k));
@@ -125,10 +133,12 @@ fn get_chains<'a>(
         anchors,
         chain_heuristic,
         chain_reads,
-        h,
+        params,
         &seeds_ref,
         &seeds_q,
+        &q_order_vals,
         (0, 0),
+        seed_method,
     );
 
     //    let (mut best_seq_anchors_1, range_ref, range_query) =
@@ -137,8 +147,9 @@ fn get_chains<'a>(
         pointer_array,
         &anchors,
         &seeds_ref,
-        &seeds_q,
+        &q_orders,
         chain_reads,
+        params,
     );
 
     //Return all secondary chains for read chaining
@@ -163,8 +174,8 @@ fn get_chains<'a>(
             if seeds_ref[anchor.0 as usize].order < range_ref.0
                 || seeds_ref[anchor.0 as usize].order > range_ref.1
             {
-                if seeds_q[anchor.1 as usize].order < range_query.0
-                    || seeds_q[anchor.1 as usize].order > range_query.1
+                if q_orders[anchor.1 as usize] < range_query.0
+                    || q_orders[anchor.1 as usize] > range_query.1
                 {
                     second_round_anchors.push(*anchor);
                 }
@@ -187,10 +198,12 @@ fn get_chains<'a>(
             &mut second_round_anchors,
             chain_heuristic,
             chain_reads,
-            h,
+            params,
             &seeds_ref,
             &seeds_q,
+            &q_order_vals,
             (range_ref.1, range_query.1),
+            seed_method,
         );
 
         let (mut best_seq_anchors_2, _range_ref, _range_query, second_best_aln_score) = mem::take(
@@ -199,21 +212,13 @@ fn get_chains<'a>(
                 pointer_array,
                 &second_round_anchors,
                 &seeds_ref,
-                &seeds_q,
+                &q_orders,
                 chain_reads,
+                params,
             )[0],
         );
 
         best_seq_anchors_1.append(&mut best_seq_anchors_2);
-        //Reverse back because we may not want q reversed if the best alignment was actually forward.
-        if forward_strand == false {
-            for node in seeds_q.iter_mut() {
-                node.order = q_len as u32 - node.order - 1;
-                //            for child_id in node.child_nodes.iter_mut(){
-                //                *child_id = q_len as u32 - *child_id - 1;
-                //            }
-            }
-        }
         //    best_seq_anchors_1.sort_by(|a, b| a.0.cmp(&b.0));
         return vec![(best_seq_anchors_1, second_best_aln_score + best_aln_score)];
     }
@@ -277,14 +282,15 @@ pub fn anchors_from_seeds(
 
 pub fn chain_seeds<'a>(
     seeds_ref: &'a Vec<KmerNode>,
-    seeds_q: &'a mut Vec<KmerNode>,
+    seeds_q: &'a Vec<KmerNode>,
     ref_hash_map: &'a FxHashMap<Kmer16, Vec<u32>>,
     q_hash_map: &'a FxHashMap<Kmer16, Vec<u32>>,
-    h: usize,
+    params: &Params,
     chain_heuristic: bool,
     chain_reads: bool,
     not_used_kmers: &FxHashSet<Kmer16>,
     circular: bool,
+    seed_method: SeedMethod,
 ) -> Vec<(Anchors, f64, bool)> {
     let q_len = seeds_q.len();
     let now = Instant::now();
@@ -299,13 +305,13 @@ pub fn chain_seeds<'a>(
             false,
         );
 
-    let mut forward_strand;
+    let forward_strand;
     let ambig;
-    if num_forward_anchors as f64 > num_backward_anchors as f64 * constants::AMBIGUOUS_FRACTION {
+    if num_forward_anchors as f64 > num_backward_anchors as f64 * params.ambiguous_fraction {
         forward_strand = true;
         ambig = false;
     } else if num_backward_anchors as f64
-        > num_forward_anchors as f64 * constants::AMBIGUOUS_FRACTION
+        > num_forward_anchors as f64 * params.ambiguous_fraction
     {
         forward_strand = false;
         ambig = false;
@@ -331,12 +337,13 @@ pub fn chain_seeds<'a>(
         let mut chains_scores = get_chains(
             seeds_ref,
             seeds_q,
-            h,
+            params,
             chain_heuristic,
             chain_reads,
             circular,
             forward_strand,
             &mut anchors,
+            seed_method,
         );
         return chains_scores
             .iter_mut()
@@ -350,27 +357,34 @@ pub fn chain_seeds<'a>(
         } else {
             //            chain_heuristic = false;
         }
-        forward_strand = true;
-        let chains_scores_forward = get_chains(
-            seeds_ref,
-            seeds_q,
-            h,
-            chain_heuristic,
-            chain_reads,
-            circular,
-            forward_strand,
-            &mut forward_anchors,
-        );
-        forward_strand = false;
-        let chains_scores_backward = get_chains(
-            seeds_ref,
-            seeds_q,
-            h,
-            chain_heuristic,
-            chain_reads,
-            circular,
-            forward_strand,
-            &mut backward_anchors,
+        //Both strands only read the query seeds, so they can be chained concurrently.
+        let (chains_scores_forward, chains_scores_backward) = rayon::join(
+            || {
+                get_chains(
+                    seeds_ref,
+                    seeds_q,
+                    params,
+                    chain_heuristic,
+                    chain_reads,
+                    circular,
+                    true,
+                    &mut forward_anchors,
+                    seed_method,
+                )
+            },
+            || {
+                get_chains(
+                    seeds_ref,
+                    seeds_q,
+                    params,
+                    chain_heuristic,
+                    chain_reads,
+                    circular,
+                    false,
+                    &mut backward_anchors,
+                    seed_method,
+                )
+            },
         );
         let mut return_chains = vec![];
         for (chain, score) in chains_scores_forward.into_iter() {
@@ -452,6 +466,7 @@ pub fn get_best_path_from_chain_rewrite(
     query_nodes: &Vec<KmerNode>,
     read_length: usize,
     is_primary_ref: bool,
+    params: &Params,
 ) -> (Vec<Color>, Vec<(Anchors, f64)>) {
     let mut colour_paths: FxHashMap<usize, (f64, usize, usize, usize)> = FxHashMap::default();
 
@@ -486,7 +501,7 @@ pub fn get_best_path_from_chain_rewrite(
                         .abs();
                     let ref_dist = tup.3;
                     let gap_cost = ((query_dist as i64).abs() - ref_dist as i64).abs() as f64;
-                    let new_score_add = (constants::PATH_CHAIN_BASE_SCORE - gap_cost) as f64;
+                    let new_score_add = (params.path_chain_base_score - gap_cost) as f64;
                     tup.0 += new_score_add;
                     tup.2 = current_anchor_id;
                     tup.3 = 0;
@@ -548,20 +563,20 @@ pub fn get_best_path_from_chain_rewrite(
     colour_paths_vec.sort_by(|x, y| y.1 .0.partial_cmp(&x.1 .0).unwrap());
     let best_path_score = colour_paths_vec[0].1 .0;
 
-    if best_path_score < (anchors.len() as f64) * constants::PATH_THRESHOLD_FRACTION
+    if best_path_score < (anchors.len() as f64) * params.path_threshold_fraction
         && !is_primary_ref
     {
         println!(
             "Best path {} < {} is bad",
             best_path_score,
-            anchors.len() as f64 * constants::PATH_THRESHOLD_FRACTION
+            anchors.len() as f64 * params.path_threshold_fraction
         );
         return (vec![], vec![]);
-    } else if best_path_score < -(query_nodes.len() as f64) * constants::PATH_THRESHOLD_FRACTION {
+    } else if best_path_score < -(query_nodes.len() as f64) * params.path_threshold_fraction {
         println!(
             "Best path {} < {} is bad",
             best_path_score,
-            anchors.len() as f64 * constants::PATH_THRESHOLD_FRACTION
+            anchors.len() as f64 * params.path_threshold_fraction
         );
         return (vec![], vec![]);
     }
@@ -594,6 +609,112 @@ pub fn get_best_path_from_chain_rewrite(
     return (best_path_colors, consistent_color_anchors);
 }
 
+//Li-Stephens style Viterbi over colours along the anchors of a chain. The
+//path may switch colour at any anchor for switch_penalty, and an anchor whose
+//node lacks the current colour costs path_chain_base_score.
+pub fn get_colour_switching_path(
+    anchors: &Anchors,
+    ref_nodes: &Vec<KmerNode>,
+    query_nodes: &Vec<KmerNode>,
+    num_colours: usize,
+    params: &Params,
+) -> Vec<ColourSegment> {
+    if anchors.len() == 0 || num_colours == 0 {
+        return vec![];
+    }
+
+    let mut scores = vec![0.; num_colours];
+    let mut pointers: Vec<Vec<u8>> = vec![];
+    for (i, anchor) in anchors.iter().enumerate() {
+        let node_color = ref_nodes[anchor.0 as usize].color;
+        let mut best_prev = 0;
+        for c in 0..num_colours {
+            if scores[c] > scores[best_prev] {
+                best_prev = c;
+            }
+        }
+        let switch_score = scores[best_prev] - params.switch_penalty;
+        let mut new_scores = vec![0.; num_colours];
+        let mut new_pointers = vec![0; num_colours];
+        for c in 0..num_colours {
+            if i == 0 || scores[c] >= switch_score {
+                new_scores[c] = scores[c];
+                new_pointers[c] = c as u8;
+            } else {
+                new_scores[c] = switch_score;
+                new_pointers[c] = best_prev as u8;
+            }
+            if node_color & (1 << c) == 0 {
+                new_scores[c] -= params.path_chain_base_score;
+            }
+        }
+        scores = new_scores;
+        pointers.push(new_pointers);
+    }
+
+    let mut best_last = 0;
+    for c in 0..num_colours {
+        if scores[c] > scores[best_last] {
+            best_last = c;
+        }
+    }
+    let mut states = vec![0; anchors.len()];
+    states[anchors.len() - 1] = best_last;
+    for i in (1..anchors.len()).rev() {
+        states[i - 1] = pointers[i][states[i]] as usize;
+    }
+
+    let q_pos = |i: usize| query_nodes[anchors[i].1 as usize].actual_ref_positions[0];
+    let mut segments = vec![];
+    let mut start = 0;
+    for i in 1..anchors.len() + 1 {
+        if i < anchors.len() && states[i] == states[start] {
+            continue;
+        }
+        let color: Color = 1 << states[start];
+        let mut consistent_colors = Color::MAX;
+        let mut num_supporting = 0;
+        let mut qstart = usize::MAX;
+        let mut qend = 0;
+        for j in start..i {
+            let node_color = ref_nodes[anchors[j].0 as usize].color;
+            consistent_colors &= node_color;
+            if node_color & color != 0 {
+                num_supporting += 1;
+            }
+            qstart = usize::min(qstart, q_pos(j));
+            qend = usize::max(qend, q_pos(j) + constants::K);
+        }
+        let breakpoint;
+        if start == 0 {
+            breakpoint = None;
+        } else {
+            let left = q_pos(start - 1);
+            let right = q_pos(start);
+            breakpoint = Some((usize::min(left, right), usize::max(left, right)));
+        }
+        segments.push(ColourSegment {
+            start_anchor: start,
+            end_anchor: i - 1,
+            color: color,
+            consistent_colors: consistent_colors,
+            qstart: qstart,
+            qend: qend,
+            breakpoint: breakpoint,
+            num_supporting: num_supporting,
+            support: num_supporting as f64 / (i - start) as f64,
+        });
+        start = i;
+    }
+
+    println!(
+        "Colour switching path has {} segments over {} anchors",
+        segments.len(),
+        anchors.len()
+    );
+    return segments;
+}
+
 pub fn get_best_path_from_chain2(
     anchors: &Anchors,
     ref_nodes: &Vec<KmerNode>,
@@ -601,6 +722,7 @@ pub fn get_best_path_from_chain2(
     query_nodes: &Vec<KmerNode>,
     read_length: usize,
     is_primary_ref: bool,
+    params: &Params,
 ) -> (Vec<Color>, Vec<(Anchors, f64)>) {
     let mut in_edges_dict: FxHashMap<u32, Vec<u32>> = FxHashMap::default();
     let mut best_paths: FxHashMap<u32, Vec<(Color, f64, usize, usize)>> = FxHashMap::default();
@@ -721,7 +843,7 @@ pub fn get_best_path_from_chain2(
                         //                        let ref_dist = parent_path.2 + 1;
                         let ref_dist = parent_path.2 + bp_ref_distance;
                         let gap_cost = ((query_dist as i64).abs() - ref_dist as i64).abs() as f64;
-                        let new_score_add = (constants::PATH_CHAIN_BASE_SCORE - gap_cost) as f64;
+                        let new_score_add = (params.path_chain_base_score - gap_cost) as f64;
                         let updated_path =
                             (new_color, parent_path.1 + new_score_add, 0, parent_path.3);
                         best_node_paths.push(updated_path);
@@ -830,20 +952,20 @@ pub fn get_best_path_from_chain2(
         .unwrap()
         .1;
 
-    if best_path_score < (anchors.len() as f64) * constants::PATH_THRESHOLD_FRACTION
+    if best_path_score < (anchors.len() as f64) * params.path_threshold_fraction
         && !is_primary_ref
     {
         println!(
             "Best path {} < {} is bad",
             best_path_score,
-            anchors.len() as f64 * constants::PATH_THRESHOLD_FRACTION
+            anchors.len() as f64 * params.path_threshold_fraction
         );
         return (vec![], vec![]);
-    } else if best_path_score < -(query_nodes.len() as f64) * constants::PATH_THRESHOLD_FRACTION {
+    } else if best_path_score < -(query_nodes.len() as f64) * params.path_threshold_fraction {
         println!(
             "Best path {} < {} is bad",
             best_path_score,
-            anchors.len() as f64 * constants::PATH_THRESHOLD_FRACTION
+            anchors.len() as f64 * params.path_threshold_fraction
         );
         return (vec![], vec![]);
     }
@@ -1102,69 +1224,124 @@ fn modulo_n(value: u32, n: u32, modulo_position: u32) -> usize {
     }
 }
 
+//Best predecessor in tree of anchor i at query position anchoriq: the highest
+//tree value at or before it on another reference seed and query seed.
+fn best_predecessor(
+    tree: &SearchTree<[usize; 2]>,
+    anchoriq: usize,
+    i: usize,
+    anchor: &(u32, u32),
+) -> Option<(f64, usize)> {
+    let (best_score, best_id) = tree.mrq([0, 0], [anchoriq, i], anchor.0 as usize, anchor.1 as usize);
+    if best_score == i64::MIN {
+        return None;
+    }
+    return Some((best_score as f64, best_id));
+}
+
+//The larger of two (score, anchor) pairs. Ties go to the later anchor, as
+//in SearchTree::mrq.
+fn max_pair(a: (f64, usize), b: (f64, usize)) -> (f64, usize) {
+    if a.0 > b.0 || (a.0 == b.0 && a.1 > b.1) {
+        a
+    } else {
+        b
+    }
+}
+
 pub fn score_anchors(
     f: &mut Vec<f64>,
     pointer_array: &mut [usize],
     anchors: &Anchors,
     chain_heuristic: bool,
     chain_reads: bool,
-    h: usize,
+    params: &Params,
     seeds_ref: &Vec<KmerNode>,
     seeds_q: &Vec<KmerNode>,
+    q_order_vals: &[u32],
     modulo_positions: (u32, u32),
+    seed_method: SeedMethod,
 ) {
     let q_len = seeds_q.len() as u32;
     let r_len = seeds_ref.len() as u32;
     let mut ref_color_vec = vec![0; anchors.len()];
-    if chain_reads && chain_heuristic {
+    if chain_reads {
         for (i, anchor) in anchors.iter().enumerate() {
             ref_color_vec[i] = seeds_ref[anchor.0 as usize].color;
         }
     }
+    //With colour chaining, ref_color_vec becomes the colours shared by every
+    //anchor of the best chain ending at each anchor. Genomes and contigs are
+    //always chained without regard to colour.
+    let colour_chaining;
+    if chain_reads {
+        colour_chaining = params.colour_chaining;
+    } else {
+        colour_chaining = ColourChaining::Blind;
+    }
     //    let n = 1;
     let w;
     if chain_reads {
-        w = constants::READ_CHAIN_BASE_SCORE;
+        w = params.read_chain_base_score;
     } else {
-        w = constants::CONTIG_CHAIN_BASE_SCORE;
+        w = params.contig_chain_base_score;
     }
+    let h = params.h;
     let c1 = 1.0;
 
     //    let mut last_best_j = usize::MAX;
+    let use_tree = !chain_reads || !chain_heuristic;
     let mut avl_tree: SearchTree<[usize; 2]> = SearchTree::new();
-    if !chain_reads || !chain_heuristic {
+    //One more tree per colour holding the anchors on seeds of that colour.
+    //An anchor's score only goes into the trees of its chain's colours.
+    let mut colour_trees: Vec<SearchTree<[usize; 2]>> = vec![];
+    if use_tree {
         for (i, anchor) in anchors.iter().enumerate() {
             avl_tree.insert([
-                modulo_n(
-                    seeds_q[anchor.1 as usize].order_val,
-                    q_len,
-                    modulo_positions.1,
-                ),
+                modulo_n(q_order_vals[anchor.1 as usize], q_len, modulo_positions.1),
                 i,
             ]);
         }
+        if colour_chaining != ColourChaining::Blind {
+            let all_colours = ref_color_vec.iter().fold(0, |acc, x| acc | x);
+            let num_colours = (Color::BITS - all_colours.leading_zeros()) as usize;
+            colour_trees = (0..num_colours).map(|_| SearchTree::new()).collect();
+            for (i, anchor) in anchors.iter().enumerate() {
+                for bit in align::get_nonzero_bits_fast(ref_color_vec[i]) {
+                    colour_trees[bit].insert([
+                        modulo_n(q_order_vals[anchor.1 as usize], q_len, modulo_positions.1),
+                        i,
+                    ]);
+                }
+            }
+        }
+
+        let anchor0q = modulo_n(q_order_vals[anchors[0].1 as usize], q_len, modulo_positions.1);
+        let tree_value = c1
+            * (anchor0q
+                + modulo_n(
+                    seeds_ref[anchors[0].0 as usize].order_val,
+                    r_len,
+                    modulo_positions.0,
+                )) as f64;
         avl_tree.update_query_info(
-            [
-                modulo_n(
-                    seeds_q[anchors[0].1 as usize].order_val,
-                    q_len,
-                    modulo_positions.1,
-                ),
-                0,
-            ],
-            c1 * (modulo_n(
-                seeds_q[anchors[0].1 as usize].order_val,
-                q_len,
-                modulo_positions.1,
-            ) + modulo_n(
-                seeds_ref[anchors[0].0 as usize].order_val,
-                r_len,
-                modulo_positions.0,
-            )) as f64,
+            [anchor0q, 0],
+            tree_value,
             0,
             anchors[0].0 as usize,
             anchors[0].1 as usize,
         );
+        if !colour_trees.is_empty() {
+            for bit in align::get_nonzero_bits_fast(ref_color_vec[0]) {
+                colour_trees[bit].update_query_info(
+                    [anchor0q, 0],
+                    tree_value,
+                    0,
+                    anchors[0].0 as usize,
+                    anchors[0].1 as usize,
+                );
+            }
+        }
     }
     let mandatory_color = Color::MAX;
     let adj_h = (h * anchors.len()) as f64 / 12500 as f64 + 20.;
@@ -1174,10 +1351,10 @@ pub fn score_anchors(
         let q_oval;
         let r_oval;
         if chain_heuristic && chain_reads {
-            q_oval = seeds_q[anchors[i].1 as usize].order_val;
+            q_oval = q_order_vals[anchors[i].1 as usize];
             r_oval = seeds_ref[anchors[i].0 as usize].order_val;
         } else {
-            q_oval = seeds_q[anchors[i].1 as usize].order_val;
+            q_oval = q_order_vals[anchors[i].1 as usize];
             r_oval = seeds_ref[anchors[i].0 as usize].order_val;
         }
         let anchoriq = modulo_n(q_oval, q_len, modulo_positions.1);
@@ -1193,9 +1370,9 @@ pub fn score_anchors(
                     break;
                 }
 
-                let anchorjq = seeds_q[anchors[j].1 as usize].order_val as usize;
+                let anchorjq = q_order_vals[anchors[j].1 as usize] as usize;
                 let anchorjr = seeds_ref[anchors[j].0 as usize].order_val as usize;
-                let anchoriq = seeds_q[anchors[i].1 as usize].order_val as usize;
+                let anchoriq = q_order_vals[anchors[i].1 as usize] as usize;
 
                 if anchoriq < anchorjq {
                     //panic!("Don't deal with circular mappings right now");
@@ -1216,6 +1393,7 @@ pub fn score_anchors(
                 //                }
 
                 let mut incompat_chain = false;
+                let mut colour_penalty = 0.;
 
                 if anchorjr >= anchorir {
                     incompat_chain = true;
@@ -1223,7 +1401,11 @@ pub fn score_anchors(
 
                 //This forces the chain to be a walkable path in the DAG
                 if color_history_jr & color_ir == 0 {
-                    incompat_chain = true;
+                    if colour_chaining == ColourChaining::Penalise {
+                        colour_penalty = params.colour_penalty;
+                    } else {
+                        incompat_chain = true;
+                    }
                 }
 
                 if anchorjq >= anchoriq {
@@ -1239,7 +1421,11 @@ pub fn score_anchors(
                     num_iter += 1;
                     let ref_order_dist = (anchorir - anchorjr) as f64;
                     let query_order_dist = (anchoriq - anchorjq) as f64;
-                    f_cand_i = f[j] + heuristic_score(ref_order_dist, query_order_dist)
+                    f_cand_i = f[j] + heuristic_score(
+                        ref_order_dist,
+                        query_order_dist,
+                        params.banded_chaining_base_score,
+                    ) - colour_penalty
                 }
                 if f_cand_i > best_f_i {
                     best_f_i = f_cand_i;
@@ -1247,33 +1433,53 @@ pub fn score_anchors(
                 }
             }
         } else {
-            let gap_start = 0;
-            let (best_score, best_id) = avl_tree.mrq(
-                [gap_start as usize, 0],
-                [anchoriq, i],
-                anchors[i].0 as usize,
-                anchors[i].1 as usize,
-            );
-
-            if best_score == i64::MIN {
-                best_f_i = 0.0;
-                best_j = i;
+            let best;
+            if colour_chaining == ColourChaining::Blind {
+                best = best_predecessor(&avl_tree, anchoriq, i, &anchors[i]);
             } else {
+                //Best predecessor whose chain shares a colour with the anchor,
+                //or with Penalise any predecessor at a cost
+                let mut best_coherent = None;
+                for bit in align::get_nonzero_bits_fast(ref_color_vec[i]) {
+                    if let Some(cand) = best_predecessor(&colour_trees[bit], anchoriq, i, &anchors[i]) {
+                        if let Some(best_so_far) = best_coherent {
+                            best_coherent = Some(max_pair(best_so_far, cand));
+                        } else {
+                            best_coherent = Some(cand);
+                        }
+                    }
+                }
+                let mut best_any = None;
+                if colour_chaining == ColourChaining::Penalise {
+                    if let Some((score, id)) = best_predecessor(&avl_tree, anchoriq, i, &anchors[i]) {
+                        best_any = Some((score - params.colour_penalty, id));
+                    }
+                }
+                if let (Some(coherent), Some(any)) = (best_coherent, best_any) {
+                    best = Some(max_pair(coherent, any));
+                } else {
+                    best = best_coherent.or(best_any);
+                }
+            }
+            if let Some((best_score, best_id)) = best {
                 best_j = best_id;
                 if chain_reads {
-                    best_f_i = best_score as f64 - c1 * (anchorir + anchoriq) as f64 + w;
+                    best_f_i = best_score - c1 * (anchorir + anchoriq) as f64 + w;
                 } else {
-                    best_f_i = best_score as f64 - c1 * (anchorir + anchoriq) as f64 + w;
+                    best_f_i = best_score - c1 * (anchorir + anchoriq) as f64 + w;
                 }
 
                 if best_f_i < 0.0 {
                     best_f_i = 0.0;
                     best_j = i;
                 }
+            } else {
+                best_f_i = 0.0;
+                best_j = i;
             }
             if anchoriq
                 < modulo_n(
-                    seeds_q[anchors[best_j].1 as usize].order_val,
+                    q_order_vals[anchors[best_j].1 as usize],
                     q_len,
                     modulo_positions.1,
                 )
@@ -1283,24 +1489,50 @@ pub fn score_anchors(
             }
         }
 
+        //Strobemer anchors whose strobes are spaced differently on the
+        //query and the reference hide an indel between the strobes. HPC seeds
+        //also span more than k, but there a different span is the homopolymer
+        //error the compression absorbs.
+        if seed_method == SeedMethod::Strobemer && best_j != i && best_j != usize::MAX {
+            let span_r = seeds_ref[anchors[i].0 as usize].span as f64;
+            let span_q = seeds_q[anchors[i].1 as usize].span as f64;
+            best_f_i -= (span_r - span_q).abs();
+        }
+
         //        last_best_j = best_j;
         if best_f_i <= 0.0 {
             best_j = i
         }
         f.push(best_f_i);
-        if !chain_heuristic || !chain_reads {
+        if best_j != usize::MAX {
+            pointer_array[i] = best_j;
+            if colour_chaining != ColourChaining::Blind && best_j != i {
+                let shared_colours = ref_color_vec[i] & ref_color_vec[best_j];
+                if shared_colours != 0 {
+                    ref_color_vec[i] = shared_colours;
+                }
+            }
+        }
+        if use_tree {
+            let tree_value = best_f_i + w + c1 * (anchorir + anchoriq) as f64;
             avl_tree.update_query_info(
                 [anchoriq, i],
-                best_f_i + w + c1 * (anchorir + anchoriq) as f64,
+                tree_value,
                 i,
                 anchors[i].0 as usize,
                 anchors[i].1 as usize,
             );
-        }
-        if best_j != usize::MAX {
-            pointer_array[i] = best_j;
-            //TODO
-            //            ref_color_vec[i] &= ref_color_vec[best_j];
+            if !colour_trees.is_empty() {
+                for bit in align::get_nonzero_bits_fast(ref_color_vec[i]) {
+                    colour_trees[bit].update_query_info(
+                        [anchoriq, i],
+                        tree_value,
+                        i,
+                        anchors[i].0 as usize,
+                        anchors[i].1 as usize,
+                    );
+                }
+            }
         }
     }
 }
@@ -1310,8 +1542,9 @@ pub fn get_best_chains(
     pointer_array: Vec<usize>,
     anchors: &Anchors,
     seeds_ref: &Vec<KmerNode>,
-    seeds_q: &Vec<KmerNode>,
+    q_orders: &[u32],
     chain_reads: bool,
+    params: &Params,
 ) -> Vec<(Anchors, (u32, u32), (u32, u32), f64)> {
     //    for anchor in anchors.iter(){
     //        dbg!(align::get_nonzero_bits(seeds_ref[anchor.0 as usize].color));
@@ -1322,8 +1555,8 @@ pub fn get_best_chains(
     let mut return_chains: Vec<(Anchors, (u32, u32), (u32, u32), f64)> = vec![];
     let mut already_used_anchors = FxHashSet::default();
     let best_chain_score = f[vec[0].0];
-    let cutoff_percent = constants::SECONDARY_CHAIN_CUTOFF_PERCENT;
-    let cutoff_score = constants::CHAIN_CUTOFF_SCORE;
+    let cutoff_percent = params.secondary_chain_cutoff_percent;
+    let cutoff_score = params.chain_cutoff_score;
     if best_chain_score < cutoff_score {
         println!("Poor chaining score {}", best_chain_score);
         return vec![];
@@ -1407,8 +1640,8 @@ pub fn get_best_chains(
             seeds_ref[anchors[chain_sequence[chain_sequence.len() - 1]].0 as usize].order;
 
         println!("End/start of ref anchors: {},{}", ref_end, ref_start);
-        let q_end = seeds_q[anchors[chain_sequence[0]].1 as usize].order;
-        let q_start = seeds_q[anchors[chain_sequence[chain_sequence.len() - 1]].1 as usize].order;
+        let q_end = q_orders[anchors[chain_sequence[0]].1 as usize];
+        let q_start = q_orders[anchors[chain_sequence[chain_sequence.len() - 1]].1 as usize];
 
         println!("End/start of query anchors: {},{}", q_end, q_start);
 
@@ -1429,11 +1662,349 @@ pub fn get_best_chains(
             seeds_ref[last_anchor.0 as usize].order,
         );
         let range_query = (
-            seeds_q[first_anchor.1 as usize].order,
-            seeds_q[last_anchor.1 as usize].order,
+            q_orders[first_anchor.1 as usize],
+            q_orders[last_anchor.1 as usize],
         );
         return_chains.push((best_seq_anchors, range_ref, range_query, ith_score));
     }
 
     return return_chains;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph_utils;
+    use crate::seeding_methods_bit;
+    use debruijn::dna_string::DnaString;
+    use rand::{Rng, SeedableRng};
+
+    fn seed(order: u32, order_val: u32) -> KmerNode {
+        KmerNode {
+            kmer: Kmer16::from_u64(0),
+            order: order,
+            order_val: order_val,
+            color: 1,
+            child_nodes: SmallVec::new(),
+            child_edge_distance: SmallVec::new(),
+            id: order,
+            canonical: true,
+            actual_ref_positions: SmallVec::new(),
+            repetitive: false,
+            primary_base: None,
+            span: 16,
+        }
+    }
+
+    //Colour chaining only differs from colour-blind chaining when the seeds
+    //have different colours
+    #[test]
+    fn colour_chaining_without_colours_is_blind() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+        for _ in 0..20 {
+            let mut seeds_ref = vec![];
+            let mut seeds_q = vec![];
+            let (mut ref_pos, mut q_pos) = (0, 0);
+            for i in 0..300 {
+                seeds_ref.push(seed(i, ref_pos));
+                seeds_q.push(seed(i, q_pos));
+                ref_pos += rng.gen_range(1..30);
+                //Some query seeds share a position
+                q_pos += rng.gen_range(0..30);
+            }
+            //A noisy diagonal with repeats on both sides
+            let mut anchors = vec![];
+            for i in 0..300 {
+                if rng.gen_bool(0.8) {
+                    let q = (i as i64 + rng.gen_range(-3..4)).max(0).min(299);
+                    anchors.push((i as u32, q as u32));
+                }
+                if rng.gen_bool(0.3) {
+                    anchors.push((i as u32, rng.gen_range(0..300)));
+                }
+            }
+            anchors.sort();
+            anchors.dedup();
+
+            let q_order_vals: Vec<u32> = seeds_q.iter().map(|x| x.order_val).collect();
+            let mut results = vec![];
+            for colour_chaining in
+                [ColourChaining::Blind, ColourChaining::Strict, ColourChaining::Penalise]
+            {
+                let mut params = constants::default_params();
+                params.colour_chaining = colour_chaining;
+                let mut f = vec![0.];
+                let mut pointer_array: Vec<usize> = (0..anchors.len()).collect();
+                score_anchors(
+                    &mut f,
+                    &mut pointer_array,
+                    &anchors,
+                    false,
+                    true,
+                    &params,
+                    &seeds_ref,
+                    &seeds_q,
+                    &q_order_vals,
+                    (0, 0),
+                    SeedMethod::Minimizer,
+                );
+                results.push((f, pointer_array));
+            }
+            assert_eq!(results[0], results[1]);
+            assert_eq!(results[0], results[2]);
+        }
+    }
+
+    //Only strobemer spans cost chain score; HPC seeds on the query span more
+    //bases wherever a homopolymer is longer
+    #[test]
+    fn only_strobemer_spans_are_penalised() {
+        let params = constants::default_params();
+        let mut seeds_ref = vec![];
+        let mut seeds_q = vec![];
+        for i in 0..50 {
+            seeds_ref.push(seed(i, 20 * i));
+            let mut q_seed = seed(i, 20 * i);
+            q_seed.span = 16 + (i % 3) as u16;
+            seeds_q.push(q_seed);
+        }
+        let anchors: Anchors = (0..50).map(|i| (i, i)).collect();
+        let q_order_vals: Vec<u32> = seeds_q.iter().map(|x| x.order_val).collect();
+        let mut scores = vec![];
+        for seed_method in [SeedMethod::Minimizer, SeedMethod::Strobemer] {
+            let mut f = vec![0.];
+            let mut pointer_array: Vec<usize> = (0..anchors.len()).collect();
+            score_anchors(
+                &mut f,
+                &mut pointer_array,
+                &anchors,
+                false,
+                false,
+                &params,
+                &seeds_ref,
+                &seeds_q,
+                &q_order_vals,
+                (0, 0),
+                seed_method,
+            );
+            assert_eq!(pointer_array[49], 48);
+            scores.push(f[49]);
+        }
+        let penalty: u32 = (1..50).map(|i| i % 3).sum();
+        assert_eq!(scores[0] - scores[1], penalty as f64);
+    }
+
+    #[test]
+    fn strict_colour_chains_share_a_colour() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+        let mut params = constants::default_params();
+        params.colour_chaining = ColourChaining::Strict;
+        for chain_heuristic in [false, true] {
+            let mut seeds_ref = vec![];
+            let mut seeds_q = vec![];
+            let (mut ref_pos, mut q_pos) = (0, 0);
+            for i in 0..300 {
+                let mut ref_seed = seed(i, ref_pos);
+                ref_seed.color = rng.gen_range(1..16);
+                seeds_ref.push(ref_seed);
+                seeds_q.push(seed(i, q_pos));
+                ref_pos += rng.gen_range(1..30);
+                q_pos += rng.gen_range(1..30);
+            }
+            let mut anchors: Anchors = (0..300).map(|i| (i, i)).collect();
+            anchors.retain(|_| rng.gen_bool(0.8));
+
+            let mut f = vec![0.];
+            let mut pointer_array: Vec<usize> = (0..anchors.len()).collect();
+            let q_order_vals: Vec<u32> = seeds_q.iter().map(|x| x.order_val).collect();
+            score_anchors(
+                &mut f,
+                &mut pointer_array,
+                &anchors,
+                chain_heuristic,
+                true,
+                &params,
+                &seeds_ref,
+                &seeds_q,
+                &q_order_vals,
+                (0, 0),
+                SeedMethod::Minimizer,
+            );
+            for i in 0..anchors.len() {
+                let mut colour = seeds_ref[anchors[i].0 as usize].color;
+                let mut j = i;
+                while pointer_array[j] != j {
+                    j = pointer_array[j];
+                    colour &= seeds_ref[anchors[j].0 as usize].color;
+                }
+                assert_ne!(colour, 0);
+            }
+        }
+    }
+
+    //score_anchors for genomes as it was before colour chaining
+    fn mrq_score_anchors(
+        f: &mut Vec<f64>,
+        pointer_array: &mut [usize],
+        anchors: &Anchors,
+        params: &Params,
+        seeds_ref: &Vec<KmerNode>,
+        seeds_q: &Vec<KmerNode>,
+        q_order_vals: &[u32],
+    ) {
+        let modulo_positions = (0, 0);
+        let q_len = seeds_q.len() as u32;
+        let r_len = seeds_ref.len() as u32;
+        let w = params.contig_chain_base_score;
+        let c1 = 1.0;
+        let mut avl_tree: SearchTree<[usize; 2]> = SearchTree::new();
+        for (i, anchor) in anchors.iter().enumerate() {
+            avl_tree.insert([
+                modulo_n(q_order_vals[anchor.1 as usize], q_len, modulo_positions.1),
+                i,
+            ]);
+        }
+        avl_tree.update_query_info(
+            [modulo_n(q_order_vals[anchors[0].1 as usize], q_len, modulo_positions.1), 0],
+            c1 * (modulo_n(q_order_vals[anchors[0].1 as usize], q_len, modulo_positions.1)
+                + modulo_n(seeds_ref[anchors[0].0 as usize].order_val, r_len, modulo_positions.0))
+                as f64,
+            0,
+            anchors[0].0 as usize,
+            anchors[0].1 as usize,
+        );
+        for i in 1..anchors.len() {
+            let anchoriq = modulo_n(q_order_vals[anchors[i].1 as usize], q_len, modulo_positions.1);
+            let anchorir =
+                modulo_n(seeds_ref[anchors[i].0 as usize].order_val, r_len, modulo_positions.0);
+            let mut best_f_i;
+            let mut best_j;
+            let (best_score, best_id) = avl_tree.mrq(
+                [0, 0],
+                [anchoriq, i],
+                anchors[i].0 as usize,
+                anchors[i].1 as usize,
+            );
+            if best_score == i64::MIN {
+                best_f_i = 0.0;
+                best_j = i;
+            } else {
+                best_j = best_id;
+                best_f_i = best_score as f64 - c1 * (anchorir + anchoriq) as f64 + w;
+                if best_f_i < 0.0 {
+                    best_f_i = 0.0;
+                    best_j = i;
+                }
+            }
+            if best_f_i <= 0.0 {
+                best_j = i
+            }
+            f.push(best_f_i);
+            avl_tree.update_query_info(
+                [anchoriq, i],
+                best_f_i + w + c1 * (anchorir + anchoriq) as f64,
+                i,
+                anchors[i].0 as usize,
+                anchors[i].1 as usize,
+            );
+            pointer_array[i] = best_j;
+        }
+    }
+
+    //Genomes chained as in generate give the same scores and chains as before
+    #[test]
+    fn genome_chaining_matches_mrq_on_test_refs() {
+        let genomes = crate::test_utils::test_genomes(&crate::test_utils::TEST_REFS, 1000000);
+        let seed_params = crate::test_utils::minimizer_params();
+        let params = constants::default_params();
+        let no_kmers = FxHashSet::default();
+        let (mut seeds_ref, _) = seeding_methods_bit::seed_sequence(
+            &DnaString::from_acgt_bytes(&genomes[0]),
+            &seed_params,
+            30,
+            &no_kmers,
+            &FxHashMap::default(),
+            &vec![],
+            true,
+        );
+        graph_utils::top_sort(&mut seeds_ref);
+        let ref_hash_map = get_kmer_dict_mut(&mut seeds_ref);
+        for genome in genomes[1..].iter() {
+            let (seeds_q, _) = seeding_methods_bit::seed_sequence(
+                &DnaString::from_acgt_bytes(genome),
+                &seed_params,
+                1,
+                &no_kmers,
+                &FxHashMap::default(),
+                &vec![],
+                false,
+            );
+            let q_hash_map = get_kmer_dict(&seeds_q);
+            let (forward_anchors, backward_anchors, _, _) = anchors_from_seeds(
+                &seeds_ref,
+                &seeds_q,
+                &ref_hash_map,
+                &q_hash_map,
+                &no_kmers,
+                false,
+            );
+            for (forward_strand, mut anchors) in
+                [(true, forward_anchors), (false, backward_anchors)]
+            {
+                let (q_orders, q_order_vals) = strand_orders(&seeds_q, forward_strand);
+                anchors.sort_by(|a, b| {
+                    seeds_ref[a.0 as usize]
+                        .order
+                        .cmp(&seeds_ref[b.0 as usize].order)
+                });
+                let mut f = vec![0.];
+                let mut pointer_array: Vec<usize> = (0..anchors.len()).collect();
+                score_anchors(
+                    &mut f,
+                    &mut pointer_array,
+                    &anchors,
+                    false,
+                    false,
+                    &params,
+                    &seeds_ref,
+                    &seeds_q,
+                    &q_order_vals,
+                    (0, 0),
+                    SeedMethod::Minimizer,
+                );
+                let mut mrq_f = vec![0.];
+                let mut mrq_pointer_array: Vec<usize> = (0..anchors.len()).collect();
+                mrq_score_anchors(
+                    &mut mrq_f,
+                    &mut mrq_pointer_array,
+                    &anchors,
+                    &params,
+                    &seeds_ref,
+                    &seeds_q,
+                    &q_order_vals,
+                );
+                assert_eq!(f, mrq_f);
+                assert_eq!(pointer_array, mrq_pointer_array);
+                let chains = get_best_chains(
+                    f,
+                    pointer_array,
+                    &anchors,
+                    &seeds_ref,
+                    &q_orders,
+                    false,
+                    &params,
+                );
+                let mrq_chains = get_best_chains(
+                    mrq_f,
+                    mrq_pointer_array,
+                    &anchors,
+                    &seeds_ref,
+                    &q_orders,
+                    false,
+                    &params,
+                );
+                assert_eq!(chains, mrq_chains);
+            }
+        }
+    }
+}
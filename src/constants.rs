@@ -1,16 +1,95 @@
-pub const AMBIGUOUS_FRACTION: f64 = 10./9.;
+use crate::data_structs::{ColourChaining, Params};
 
-pub const SECONDARY_CHAIN_CUTOFF_PERCENT: f64 = 0.90;
 pub const K: usize = 16;
 pub const ERROR: f64 = 0.9;
 
 pub const BASE_SCORE: f64 = 10.;
-pub const CONTIG_CHAIN_BASE_SCORE: f64 = 100.;
-pub const READ_CHAIN_BASE_SCORE: f64 = 50.;
-pub const BANDED_CHAINING_BASE_SCORE: f64 = 50.;
 
-pub const READ_LENGTH_SUPER_CHAIN_CUTOFF: usize = 3000;
-pub const CHAIN_CUTOFF_SCORE: f64 = 100.;
-pub const PATH_CHAIN_BASE_SCORE: f64 = 50.;
-pub const COORD_CHAIN_BASE_SCORE: f64 = 100.;
-pub const PATH_THRESHOLD_FRACTION: f64 = PATH_CHAIN_BASE_SCORE / 2.; 
+pub const FREQUENT_KMER_MIN_COUNT: usize = 10;
+
+//Identifies seeding_methods_bit::hash64; change it whenever k-mer ordering changes.
+pub const SEED_HASH: &str = "hash64-v1";
+
+pub const PRESETS: [&str; 5] = ["hifi", "ont", "clr", "sr", "asm"];
+
+//Parameters used when no preset is given; tuned for long reads.
+pub fn default_params() -> Params {
+    let path_chain_base_score = 50.;
+    Params {
+        w: 16,
+        hpc: false,
+        h: 50,
+        max_occ: 500,
+        colour_chaining: ColourChaining::Blind,
+        colour_penalty: 250.,
+        read_chain_base_score: 50.,
+        contig_chain_base_score: 100.,
+        banded_chaining_base_score: 50.,
+        coord_chain_base_score: 100.,
+        chain_cutoff_score: 100.,
+        secondary_chain_cutoff_percent: 0.90,
+        secondary_score_margin: 50.,
+        secondary_anchor_fraction: 0.05,
+        ambiguous_fraction: 10. / 9.,
+        path_chain_base_score: path_chain_base_score,
+        path_threshold_fraction: path_chain_base_score / 2.,
+        switch_penalty: 5. * path_chain_base_score,
+        read_length_super_chain_cutoff: 3000,
+        match_score: 1,
+        mismatch: 2,
+        gap_open: 2,
+        gap_extend: 1,
+        x_drop: 50,
+        block_size: 512,
+        wfa_min_wavefront_length: 10,
+        wfa_max_distance_threshold: 50,
+        graph_align_max_cells: 20_000_000,
+    }
+}
+
+//None if the preset isn't one of PRESETS.
+pub fn preset_params(preset: &str) -> Option<Params> {
+    let mut params = default_params();
+    match preset {
+        "hifi" => {
+            params.w = 20;
+            params.mismatch = 4;
+            params.gap_open = 6;
+        }
+        //Denser seeds and homopolymer compression map more simulated ONT and
+        //CLR reads
+        "ont" => {
+            params.w = 10;
+            params.hpc = true;
+        }
+        //CLR reads have more indels, so anchors are further apart: look
+        //further back for them, score them higher and keep shorter chains
+        "clr" => {
+            params.w = 10;
+            params.hpc = true;
+            params.h = 100;
+            params.read_chain_base_score = 60.;
+            params.chain_cutoff_score = 70.;
+        }
+        "sr" => {
+            params.w = 10;
+            params.h = 25;
+            params.mismatch = 4;
+            params.gap_open = 6;
+            params.block_size = 64;
+            params.x_drop = 20;
+        }
+        "asm" => {
+            params.w = 24;
+            params.h = 100;
+            params.chain_cutoff_score = 500.;
+            params.secondary_chain_cutoff_percent = 0.95;
+            params.mismatch = 4;
+            params.gap_open = 6;
+            params.x_drop = 100;
+            params.graph_align_max_cells = 100_000_000;
+        }
+        _ => return None,
+    }
+    return Some(params);
+}
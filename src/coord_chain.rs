@@ -3,7 +3,7 @@ use crate::avl_tree::SearchTree;
 use crate::chain;
 use crate::constants;
 use crate::data_structs::KmerNode;
-use crate::data_structs::{Anchors, Color};
+use crate::data_structs::{Anchors, Color, Params};
 use debruijn::kmer::Kmer16;
 use debruijn::Kmer;
 use fxhash::FxHashMap;
@@ -98,7 +98,7 @@ pub fn get_super_chains(
     seeds_q: &Vec<KmerNode>,
     ref_hash_map: &FxHashMap<Kmer16, Vec<u32>>,
     q_hash_map: &FxHashMap<Kmer16, Vec<u32>>,
-    h: usize,
+    params: &Params,
     not_used_kmers: &FxHashSet<Kmer16>,
     closest_kmer_vec: &Vec<Option<u32>>,
     samp_freq: usize,
@@ -192,7 +192,7 @@ pub fn get_super_chains(
         &mut f,
         &mut pointer_array,
         &super_anchors,
-        h,
+        params.h,
         seeds_ref,
         seeds_q,
         forward_strand,
@@ -203,8 +203,15 @@ pub fn get_super_chains(
 
     println!("Super chain chaining time {}", now.elapsed().as_secs_f32());
 
-    let mut best_superchain =
-        chain::get_best_chains(f, pointer_array, &super_anchors, seeds_ref, seeds_q, true);
+    let mut best_superchain = chain::get_best_chains(
+        f,
+        pointer_array,
+        &super_anchors,
+        seeds_ref,
+        &chain::strand_orders(seeds_q, forward_strand).0,
+        true,
+        params,
+    );
 
     if best_superchain.is_empty() {
         println!("no best superchain");
@@ -455,7 +462,7 @@ pub fn get_base_chains(
     seeds_q: &Vec<KmerNode>,
     ref_hash_map: &FxHashMap<Kmer16, Vec<u32>>,
     q_hash_map: &FxHashMap<Kmer16, Vec<u32>>,
-    h: usize,
+    params: &Params,
     not_used_kmers: &FxHashSet<Kmer16>,
     read_length: usize,
 ) -> Vec<(Anchors, f64, bool)> {
@@ -486,7 +493,7 @@ pub fn get_base_chains(
         &mut f,
         &mut pointer_array,
         &normal_anchors,
-        h,
+        params,
         seeds_ref,
         seeds_q,
         forward_strand,
@@ -495,8 +502,15 @@ pub fn get_base_chains(
 
     println!("Primary ref chain chaining time {}", now.elapsed().as_secs_f32());
 
-    let mut best_primary_ref_chain =
-        chain::get_best_chains(f, pointer_array, &normal_anchors, seeds_ref, seeds_q, true);
+    let mut best_primary_ref_chain = chain::get_best_chains(
+        f,
+        pointer_array,
+        &normal_anchors,
+        seeds_ref,
+        &chain::strand_orders(seeds_q, forward_strand).0,
+        true,
+        params,
+    );
 
     if best_primary_ref_chain.is_empty() {
         println!("no best primary ref chain");
@@ -513,13 +527,14 @@ pub fn score_primary_anchors_coords(
     f: &mut Vec<f64>,
     pointer_array: &mut [usize],
     primary_ref_anchors: &Anchors,
-    h: usize,
+    params: &Params,
     seeds_ref: &Vec<KmerNode>,
     seeds_q: &Vec<KmerNode>,
     forward_strand: bool,
     read_length: usize,
 ) {
     let mut interval_pointer_array: Vec<usize> = (0..pointer_array.len()).collect();
+    let h = params.h;
     let adj_h = (h * primary_ref_anchors.len()) as f64 / 12500 as f64 + 20.;
     let adj_h = adj_h as usize;
     let adj_h = usize::min(adj_h as usize, h * 2);
@@ -572,7 +587,7 @@ pub fn score_primary_anchors_coords(
                 //                );
                 let ref_order_dist = (anchorir - anchorjr) as f64;
                 let query_order_dist = (anchoriq - anchorjq) as f64;
-                let heur_score = heuristic_score_coord(ref_order_dist, query_order_dist);
+                let heur_score = heuristic_score_coord(ref_order_dist, query_order_dist, params.coord_chain_base_score);
 
                 f_cand_i = f[j] + heur_score;
             }
@@ -593,7 +608,7 @@ pub fn score_primary_anchors_coords(
 }
 
 #[inline]
-pub fn heuristic_score_coord(ref_order_dist: f64, query_order_dist: f64) -> f64 {
+pub fn heuristic_score_coord(ref_order_dist: f64, query_order_dist: f64, base_score: f64) -> f64 {
     //    let dist_ref = -2.0 * f64::sqrt(ref_order_dist);
     //    let max_dist = f64::max(ref_order_dist,query_order_dist);
     //    let dist_ref = -1.0 * ref_order_dist;
@@ -607,7 +622,7 @@ pub fn heuristic_score_coord(ref_order_dist: f64, query_order_dist: f64) -> f64
     //    let fuzzy_gap_cost = gap_cost.powi(2) / query_order_dist;
     //    let linear_cost = f64::sqrt(ref_order_dist) + query_order_dist;
     //    let score = constants::BANDED_CHAINING_BASE_SCORE - f64::min(gap_cost, fuzzy_gap_cost);
-    let score = constants::COORD_CHAIN_BASE_SCORE - gap_cost;
+    let score = base_score - gap_cost;
     //    let score = constants::BANDED_CHAINING_BASE_SCORE - linear_cost;
     return score;
 }
@@ -2,6 +2,8 @@ use debruijn::kmer::Kmer16;
 use smallvec::SmallVec;
 use serde::{Serialize, Deserialize};
 use block_aligner::cigar::*;
+use debruijn::dna_string::DnaString;
+use fxhash::{FxHashMap, FxHashSet};
 
 //First is ref, second is query
 pub type Anchors = Vec<(u32, u32)>;
@@ -15,16 +17,132 @@ pub struct KmerNode{
     pub order_val: u32,
     pub color: Color,
     pub child_nodes: SmallVec<[u32;1]>,
-    pub child_edge_distance: SmallVec<[(u16,(Color,u8));1]>,
+    pub child_edge_distance: SmallVec<[(u32, (Color, u8));1]>,
     pub id: u32,
     pub canonical: bool,
     pub actual_ref_positions: SmallVec<[usize;0]>,
     pub repetitive: bool,
-    pub primary_base: Option<u32>
+    pub primary_base: Option<u32>,
+    pub span: u16,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SeedMethod{
+    Minimizer,
+    OpenSyncmer,
+    ClosedSyncmer,
+    Strobemer,
+}
+
+//How the graph was seeded; stored in the graph so reads are seeded the same way.
+//w is the minimizer window, s and t the syncmer s-mer length and open syncmer offset,
+//and strobe_w_min/strobe_w_max the window for the second strobe of a strobemer.
+//hpc seeds on homopolymer compressed sequence and soft_mask leaves lowercase
+//bases unseeded. seed_hash names the hash used to order k-mers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedParams{
+    pub method: SeedMethod,
+    pub k: usize,
+    pub w: usize,
+    pub s: usize,
+    pub t: usize,
+    pub strobe_w_min: usize,
+    pub strobe_w_max: usize,
+    pub hpc: bool,
+    pub soft_mask: bool,
+    pub seed_hash: String,
+}
+
+//How read chaining treats colour. Blind leaves colour to the path finding
+//after chaining, Penalise charges colour_penalty for chaining an anchor to a
+//chain sharing no colour with it and Strict doesn't allow it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColourChaining{
+    Blind,
+    Penalise,
+    Strict,
+}
+
+//Chaining, scoring and alignment parameters, set together by a preset and
+//overridable on the command line. Anchor scores are per anchor, h is the number
+//of predecessors the chaining heuristic looks at, and the alignment scores are
+//positive penalties as in minimap2. w and hpc only apply to generate since reads
+//are seeded with the graph's SeedParams. Read k-mers with more than max_occ
+//graph hits are not used as anchors. The wfa_ parameters only apply to
+//--aligner wfa.
+#[derive(Debug, Clone)]
+pub struct Params{
+    pub w: usize,
+    pub hpc: bool,
+    pub h: usize,
+    pub max_occ: usize,
+    pub colour_chaining: ColourChaining,
+    pub colour_penalty: f64,
+    pub read_chain_base_score: f64,
+    pub contig_chain_base_score: f64,
+    pub banded_chaining_base_score: f64,
+    pub coord_chain_base_score: f64,
+    pub chain_cutoff_score: f64,
+    pub secondary_chain_cutoff_percent: f64,
+    pub secondary_score_margin: f64,
+    pub secondary_anchor_fraction: f64,
+    pub ambiguous_fraction: f64,
+    pub path_chain_base_score: f64,
+    pub path_threshold_fraction: f64,
+    pub switch_penalty: f64,
+    pub read_length_super_chain_cutoff: usize,
+    pub match_score: i8,
+    pub mismatch: i8,
+    pub gap_open: i8,
+    pub gap_extend: i8,
+    pub x_drop: i32,
+    pub block_size: usize,
+    pub wfa_min_wavefront_length: i32,
+    pub wfa_max_distance_threshold: i32,
+    pub graph_align_max_cells: usize,
+}
+
+//Bases between seeds, stored on the edges of the graph. The label of an edge
+//is the sequence from the start of the parent k-mer to the start of the child
+//k-mer in graph orientation. Labels are shared between colours through seq_pool.
+//Labels spanning a run of Ns are in gap_labels since their bases are unknown.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeqGraph{
+    pub seq_pool: Vec<DnaString>,
+    pub edge_seqs: FxHashMap<(u32, u32), SmallVec<[(Color, u32);1]>>,
+    pub genome_paths: Vec<GenomePath>,
+    pub gap_labels: FxHashSet<u32>,
+}
+
+//Enough to rebuild a genome from the edge labels. rest is everything from the
+//last seed on the path back around to the first one, and start is the graph
+//orientation position of the first seed. gaps are the runs of Ns and other
+//non-ACGT bases in genome coordinates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenomePath{
+    pub head: u32,
+    pub start: usize,
+    pub rest: DnaString,
+    pub strand: bool,
+    pub gaps: Vec<(usize, usize)>,
+}
+
+//The read is stored whole in sequence and quals; soft_clips are the bases at
+//its start and end left out of the cigar. Parts of a read split at the origin
+//of a circular genome after the first are supplementary, and alignments of
+//chains after the best are secondary, with only their first part kept.
+//score, edit_distance, md, cs and divergence are written as the AS, NM, MD,
+//cs and de tags, and sa, the other parts of a split read, as the SA tag.
 pub struct BamInfo{
     pub cigar: Vec<OpLen>,
+    pub soft_clips: (usize, usize),
+    pub supplementary: bool,
+    pub secondary: bool,
+    pub score: i32,
+    pub edit_distance: usize,
+    pub md: String,
+    pub cs: String,
+    pub divergence: f64,
     pub sequence: String,
     pub quals: Vec<u8>,
     pub qname: String,
@@ -32,4 +150,90 @@ pub struct BamInfo{
     pub ref_name: String,
     pub map_pos: i64,
     pub mapq: u8,
+    pub sa: String,
+}
+
+//path lists the GFA segments walked. path_len is their total length and the
+//alignment ends path_end bases in.
+pub struct GafInfo{
+    pub qname: String,
+    pub qlen: usize,
+    pub qstart: usize,
+    pub qend: usize,
+    pub strand: bool,
+    pub path: Vec<String>,
+    pub path_len: usize,
+    pub path_end: usize,
+    pub matches: usize,
+    pub block_len: usize,
+    pub edit_distance: usize,
+    pub mapq: u8,
+    pub cigar: Vec<OpLen>,
+    pub surject_color: Color,
+}
+
+//Position of a chain on one genome for PAF output, without a base level
+//alignment. The target interval is spanned by the chain's k-mer hits projected
+//onto the genome, matches counts the read bases they cover and score is the
+//score of the chain's path for the genome.
+pub struct PafInfo{
+    pub qname: String,
+    pub qlen: usize,
+    pub qstart: usize,
+    pub qend: usize,
+    pub strand: bool,
+    pub tname: String,
+    pub tlen: usize,
+    pub tstart: usize,
+    pub tend: usize,
+    pub matches: usize,
+    pub block_len: usize,
+    pub mapq: u8,
+    pub num_anchors: usize,
+    pub score: f64,
+    pub primary: bool,
+}
+
+//One chain of a read from Mapper::map_read. paths are the colour paths found
+//along the chain as (colour, anchors, score). segments is the colour switching
+//path when asked for. At most one path of a chain is aligned, and aligned_color
+//is its colour. primary_ref_chain marks chains from the fallback chaining on
+//the first genome's coordinates. paf_info holds the chaining-only positions of
+//the reported colour.
+pub struct Mapping{
+    pub chain: usize,
+    pub strand: bool,
+    pub score: f64,
+    pub anchors: Anchors,
+    pub paths: Vec<(Color, Anchors, f64)>,
+    pub segments: Vec<ColourSegment>,
+    pub primary_ref_chain: bool,
+    pub aligned_color: Option<Color>,
+    pub bam_info: Vec<BamInfo>,
+    pub gaf_info: Option<GafInfo>,
+    pub paf_info: Vec<PafInfo>,
+}
+
+//Everything Mapper::map_read found for one read. masked_kmers counts the read's
+//seeds left out because the graph masks their k-mer, and dropped_anchors the
+//anchors left out for k-mers with more than max_occ graph hits. Both are known
+//before chaining, so reads without a mapping have them too.
+pub struct ReadMapping{
+    pub mappings: Vec<Mapping>,
+    pub masked_kmers: usize,
+    pub dropped_anchors: usize,
+}
+
+//Run of anchors assigned to one colour by the colour switching path.
+//breakpoint is the query interval between this segment and the previous one.
+pub struct ColourSegment{
+    pub start_anchor: usize,
+    pub end_anchor: usize,
+    pub color: Color,
+    pub consistent_colors: Color,
+    pub qstart: usize,
+    pub qend: usize,
+    pub breakpoint: Option<(usize, usize)>,
+    pub num_supporting: usize,
+    pub support: f64,
 }
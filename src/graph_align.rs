@@ -0,0 +1,545 @@
+use crate::constants;
+use crate::data_structs::{Anchors, Color, GafInfo, KmerNode, Params, SeqGraph};
+use crate::graph_utils;
+use block_aligner::cigar::*;
+use debruijn::dna_string::DnaString;
+use debruijn::Mer;
+use fxhash::{FxHashMap, FxHashSet};
+use std::time::Instant;
+
+//Alignment of the read between two consecutive anchors. Path excludes the
+//starting node; labels are the pool ids of the edges taken.
+struct GapAlignment {
+    path: Vec<u32>,
+    labels: Vec<u32>,
+    ops: Vec<OpLen>,
+    edge_colors: Vec<Color>,
+    edit_distance: usize,
+    matches: usize,
+    path_len: usize,
+}
+
+//DP matrix for one labelled edge; row 0 is the column of the parent node.
+struct EdgeDp {
+    from: usize,
+    label: u32,
+    color: Color,
+    matrix: Vec<u32>,
+}
+
+fn push_op(ops: &mut Vec<OpLen>, op: Operation, len: usize) {
+    if len == 0 {
+        return;
+    }
+    if let Some(last) = ops.last_mut() {
+        if last.op == op {
+            last.len += len;
+            return;
+        }
+    }
+    ops.push(OpLen { op: op, len: len });
+}
+
+pub fn cigar_string(ops: &Vec<OpLen>) -> String {
+    let mut cg = String::new();
+    for oplen in ops.iter() {
+        let c;
+        if oplen.op == Operation::M {
+            c = 'M';
+        } else if oplen.op == Operation::I {
+            c = 'I';
+        } else {
+            c = 'D';
+        }
+        cg.push_str(&format!("{}{}", oplen.len, c));
+    }
+    return cg;
+}
+
+//Edit distance alignment of query against every path from start to end,
+//running the DP over the edge labels in topological order.
+fn align_gap(
+    ref_graph: &Vec<KmerNode>,
+    seq_graph: &SeqGraph,
+    labels: &mut FxHashMap<u32, Vec<u8>>,
+    start: u32,
+    end: u32,
+    query: &[u8],
+    max_cells: usize,
+) -> Option<GapAlignment> {
+    let end_order = ref_graph[end as usize].order;
+    if end_order <= ref_graph[start as usize].order {
+        return None;
+    }
+
+    let mut joints = vec![start];
+    let mut visited = FxHashSet::default();
+    visited.insert(start);
+    let mut i = 0;
+    while i < joints.len() {
+        let node = &ref_graph[joints[i] as usize];
+        for child in node.child_nodes.iter() {
+            let child_node = &ref_graph[*child as usize];
+            if child_node.order <= node.order || child_node.order > end_order {
+                continue;
+            }
+            if !visited.contains(child) {
+                visited.insert(*child);
+                joints.push(*child);
+            }
+        }
+        i += 1;
+    }
+    if !visited.contains(&end) {
+        return None;
+    }
+    joints.sort_by_key(|id| ref_graph[*id as usize].order);
+    let mut joint_index = FxHashMap::default();
+    for (i, id) in joints.iter().enumerate() {
+        joint_index.insert(*id, i);
+    }
+
+    let m = query.len();
+    let mut cols: Vec<Option<Vec<u32>>> = vec![None; joints.len()];
+    let mut best_in: Vec<Vec<usize>> = vec![vec![]; joints.len()];
+    cols[0] = Some((0..m as u32 + 1).collect());
+    let mut edges: Vec<EdgeDp> = vec![];
+    let mut num_cells = 0;
+
+    for x in 0..joints.len() {
+        if cols[x].is_none() {
+            continue;
+        }
+        let node = &ref_graph[joints[x] as usize];
+        for child in node.child_nodes.iter() {
+            let y;
+            if let Some(index) = joint_index.get(child) {
+                y = *index;
+            } else {
+                continue;
+            }
+            if y <= x {
+                continue;
+            }
+            let variants;
+            if let Some(v) = seq_graph.edge_seqs.get(&(node.id, *child)) {
+                variants = v;
+            } else {
+                continue;
+            }
+            for (color, pool_id) in variants.iter() {
+                //Bases in assembly gaps are unknown, don't align through them
+                if seq_graph.gap_labels.contains(pool_id) {
+                    continue;
+                }
+                let label = labels
+                    .entry(*pool_id)
+                    .or_insert_with(|| seq_graph.seq_pool[*pool_id as usize].to_bytes());
+                let n = label.len();
+                num_cells += (n + 1) * (m + 1);
+                if num_cells > max_cells {
+                    return None;
+                }
+
+                let mut matrix = vec![0; (n + 1) * (m + 1)];
+                matrix[0..m + 1].copy_from_slice(cols[x].as_ref().unwrap());
+                for i in 1..n + 1 {
+                    let row = i * (m + 1);
+                    let prev_row = (i - 1) * (m + 1);
+                    matrix[row] = matrix[prev_row] + 1;
+                    for j in 1..m + 1 {
+                        let diag = matrix[prev_row + j - 1] + (label[i - 1] != query[j - 1]) as u32;
+                        let up = matrix[prev_row + j] + 1;
+                        let left = matrix[row + j - 1] + 1;
+                        matrix[row + j] = u32::min(diag, u32::min(up, left));
+                    }
+                }
+
+                let last_row = &matrix[n * (m + 1)..(n + 1) * (m + 1)];
+                let edge_index = edges.len();
+                if cols[y].is_none() {
+                    cols[y] = Some(last_row.to_vec());
+                    best_in[y] = vec![edge_index; m + 1];
+                } else {
+                    let col = cols[y].as_mut().unwrap();
+                    for j in 0..m + 1 {
+                        if last_row[j] < col[j] {
+                            col[j] = last_row[j];
+                            best_in[y][j] = edge_index;
+                        }
+                    }
+                }
+                edges.push(EdgeDp {
+                    from: x,
+                    label: *pool_id,
+                    color: *color,
+                    matrix: matrix,
+                });
+            }
+        }
+    }
+
+    let end_index = joint_index[&end];
+    if cols[end_index].is_none() {
+        return None;
+    }
+    let edit_distance = cols[end_index].as_ref().unwrap()[m] as usize;
+
+    //Traceback, ops come out in reverse
+    let mut ops_rev = vec![];
+    let mut path_rev = vec![];
+    let mut labels_rev = vec![];
+    let mut colors_rev = vec![];
+    let mut matches = 0;
+    let mut path_len = 0;
+    let mut joint = end_index;
+    let mut j = m;
+    while joint != 0 {
+        let edge = &edges[best_in[joint][j]];
+        let label = &labels[&edge.label];
+        let mut i = label.len();
+        path_len += label.len();
+        while i > 0 {
+            let row = i * (m + 1);
+            let prev_row = (i - 1) * (m + 1);
+            let val = edge.matrix[row + j];
+            if j > 0 {
+                let mismatch = (label[i - 1] != query[j - 1]) as u32;
+                if val == edge.matrix[prev_row + j - 1] + mismatch {
+                    push_op(&mut ops_rev, Operation::M, 1);
+                    if mismatch == 0 {
+                        matches += 1;
+                    }
+                    i -= 1;
+                    j -= 1;
+                    continue;
+                }
+            }
+            if val == edge.matrix[prev_row + j] + 1 {
+                push_op(&mut ops_rev, Operation::D, 1);
+                i -= 1;
+            } else {
+                push_op(&mut ops_rev, Operation::I, 1);
+                j -= 1;
+            }
+        }
+        path_rev.push(joints[joint]);
+        labels_rev.push(edge.label);
+        colors_rev.push(edge.color);
+        joint = edge.from;
+    }
+    push_op(&mut ops_rev, Operation::I, j);
+
+    let mut ops = vec![];
+    for oplen in ops_rev.into_iter().rev() {
+        push_op(&mut ops, oplen.op, oplen.len);
+    }
+
+    return Some(GapAlignment {
+        path: path_rev.into_iter().rev().collect(),
+        labels: labels_rev.into_iter().rev().collect(),
+        ops: ops,
+        edge_colors: colors_rev.into_iter().rev().collect(),
+        edit_distance: edit_distance,
+        matches: matches,
+        path_len: path_len,
+    });
+}
+
+//Align the read between its first and last anchor to the sequence-labelled
+//graph. Each gap between consecutive anchors is aligned to all paths joining
+//the two anchored nodes.
+pub fn align_to_graph(
+    anchors: &Anchors,
+    ref_graph: &Vec<KmerNode>,
+    seq_graph: &SeqGraph,
+    read_seeds: &Vec<KmerNode>,
+    read: &DnaString,
+    read_strand: bool,
+    read_id: &String,
+    params: &Params,
+) -> Option<GafInfo> {
+    let now = Instant::now();
+    let k = constants::K;
+    if anchors.len() < 2 {
+        println!("Less than 2 anchors, no graph alignment");
+        return None;
+    }
+
+    //Everything is done in graph orientation
+    let read_bytes;
+    if read_strand {
+        read_bytes = read.to_bytes();
+    } else {
+        read_bytes = read.rc().to_bytes();
+    }
+    let mut q_positions = vec![];
+    for anchor in anchors.iter() {
+        let pos = read_seeds[anchor.1 as usize].actual_ref_positions[0];
+        if read_strand {
+            q_positions.push(pos);
+        } else {
+            q_positions.push(read.len() - pos - k);
+        }
+    }
+
+    let mut labels = FxHashMap::default();
+    let mut path = vec![];
+    let mut last_node_id = anchors[0].0;
+    let mut ops = vec![];
+    let mut edge_colors = vec![];
+    let mut edit_distance = 0;
+    let mut matches = 0;
+    let mut path_len = 0;
+    for i in 0..anchors.len() - 1 {
+        if q_positions[i + 1] < q_positions[i] {
+            println!("Anchors out of order on read, no graph alignment");
+            return None;
+        }
+        let gap = align_gap(
+            ref_graph,
+            seq_graph,
+            &mut labels,
+            anchors[i].0,
+            anchors[i + 1].0,
+            &read_bytes[q_positions[i]..q_positions[i + 1]],
+            params.graph_align_max_cells,
+        );
+        if let Some(gap) = gap {
+            for (node_id, pool_id) in gap.path.iter().zip(gap.labels.iter()) {
+                path.push(graph_utils::edge_segment_name(last_node_id, *node_id, *pool_id));
+                last_node_id = *node_id;
+            }
+            for oplen in gap.ops {
+                push_op(&mut ops, oplen.op, oplen.len);
+            }
+            edge_colors.extend(gap.edge_colors);
+            edit_distance += gap.edit_distance;
+            matches += gap.matches;
+            path_len += gap.path_len;
+        } else {
+            println!("Graph alignment failed between anchors {} and {}", i, i + 1);
+            return None;
+        }
+    }
+
+    //The last anchored k-mer closes the alignment
+    let last_node = &ref_graph[anchors.last().unwrap().0 as usize];
+    let last_kmer;
+    if last_node.canonical {
+        last_kmer = last_node.kmer;
+    } else {
+        last_kmer = last_node.kmer.rc();
+    }
+    let last_q = *q_positions.last().unwrap();
+    //A strobemer is not contiguous on the read, so it only closes the
+    //alignment when its k bases are actually there
+    let mut closing_mismatches = 0;
+    for i in 0..k {
+        if last_kmer.get(i) != read_bytes[last_q + i] {
+            closing_mismatches += 1;
+        }
+    }
+    let closing_len;
+    if last_node.span as usize == k || closing_mismatches == 0 {
+        closing_len = k;
+        matches += k - closing_mismatches;
+        edit_distance += closing_mismatches;
+    } else {
+        closing_len = 0;
+    }
+    push_op(&mut ops, Operation::M, closing_len);
+    path.push(last_node.id.to_string());
+    let path_end = path_len + closing_len;
+    path_len += k;
+
+    //Surject onto the colour that carries the most of the aligned edges
+    let mut color_counts = [0; 128];
+    for color in edge_colors.iter() {
+        let mut temp = *color;
+        while temp != 0 {
+            let bit = temp.trailing_zeros();
+            color_counts[bit as usize] += 1;
+            temp ^= 1 << bit;
+        }
+    }
+    let mut best_bit = 0;
+    for bit in 0..128 {
+        if color_counts[bit] > color_counts[best_bit] {
+            best_bit = bit;
+        }
+    }
+
+    let qstart;
+    let qend;
+    if read_strand {
+        qstart = q_positions[0];
+        qend = last_q + closing_len;
+    } else {
+        qstart = read.len() - last_q - closing_len;
+        qend = read.len() - q_positions[0];
+    }
+
+    let mut block_len = 0;
+    for oplen in ops.iter() {
+        block_len += oplen.len;
+    }
+
+    println!(
+        "Graph alignment edit distance and time: {},{}",
+        edit_distance,
+        now.elapsed().as_secs_f32()
+    );
+
+    return Some(GafInfo {
+        qname: read_id.clone(),
+        qlen: read.len(),
+        qstart: qstart,
+        qend: qend,
+        strand: read_strand,
+        path: path,
+        path_len: path_len,
+        path_end: path_end,
+        matches: matches,
+        block_len: block_len,
+        edit_distance: edit_distance,
+        mapq: 255,
+        cigar: ops,
+        surject_color: 1 << best_bit,
+    });
+}
+
+//The path names segments of the GFA written by generate. It starts on the
+//label leaving the first anchored minimizer, which is where the aligned part
+//of the read starts, so the path start is always 0.
+pub fn gaf_string(gaf_info: &GafInfo) -> String {
+    let strand;
+    if gaf_info.strand {
+        strand = '+';
+    } else {
+        strand = '-';
+    }
+    let mut path_string = String::new();
+    for segment in gaf_info.path.iter() {
+        path_string.push_str(&format!(">{}", segment));
+    }
+    return format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tNM:i:{}\tcg:Z:{}",
+        gaf_info.qname,
+        gaf_info.qlen,
+        gaf_info.qstart,
+        gaf_info.qend,
+        strand,
+        path_string,
+        gaf_info.path_len,
+        0,
+        gaf_info.path_end,
+        gaf_info.matches,
+        gaf_info.block_len,
+        gaf_info.mapq,
+        gaf_info.edit_distance,
+        cigar_string(&gaf_info.cigar)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain;
+    use crate::seeding_methods_bit;
+    use crate::test_utils;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn read_aligns_across_bubble() {
+        let base = test_utils::test_genomes(&test_utils::TEST_REFS[1..2], 130000)[0][100000..]
+            .to_ascii_uppercase();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(27);
+        let mut alt = base.clone();
+        for c in alt[15000..15300].iter_mut() {
+            *c = b"ACGT"[rng.gen_range(0..4)];
+        }
+        let seed_params = test_utils::minimizer_params();
+        let graph = test_utils::build_graph(&vec![base, alt.clone()], &seed_params);
+        let params = constants::default_params();
+
+        //The read follows the second genome through the bubble
+        let read_bytes = alt[13000..17500].to_vec();
+        let read = DnaString::from_acgt_bytes(&read_bytes);
+        let read_seeds = seeding_methods_bit::seed_sequence(
+            &read,
+            &seed_params,
+            1,
+            &graph.dont_use_kmers,
+            &graph.frequent_kmers,
+            &vec![],
+            false,
+        )
+        .0;
+        let ref_hash_map = chain::get_kmer_dict(&graph.nodes);
+        let q_hash_map = chain::get_kmer_dict(&read_seeds);
+        let (anchors, _score, read_strand) = chain::chain_seeds(
+            &graph.nodes,
+            &read_seeds,
+            &ref_hash_map,
+            &q_hash_map,
+            &params,
+            true,
+            true,
+            &FxHashSet::default(),
+            false,
+            seed_params.method,
+        )
+        .into_iter()
+        .max_by(|x, y| x.1.total_cmp(&y.1))
+        .unwrap();
+        assert!(read_strand);
+        let gaf_info = align_to_graph(
+            &anchors,
+            &graph.nodes,
+            &graph.seq_graph,
+            &read_seeds,
+            &read,
+            read_strand,
+            &"read".to_string(),
+            &params,
+        )
+        .unwrap();
+        assert!(gaf_info.qstart < 2000 && gaf_info.qend > 2300);
+        assert_eq!(gaf_info.edit_distance, 0);
+        assert_eq!(gaf_info.matches, gaf_info.qend - gaf_info.qstart);
+        assert_eq!(gaf_info.surject_color, 1);
+
+        //The path spells the aligned part of the read in the written GFA
+        let mut gfa = vec![];
+        graph_utils::write_gfa(&graph.nodes, &graph.seq_graph, &mut gfa);
+        let mut segments = FxHashMap::default();
+        let mut links = FxHashSet::default();
+        for line in String::from_utf8(gfa).unwrap().lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields[0] == "S" {
+                segments.insert(fields[1].to_string(), fields[2].to_string());
+            } else if fields[0] == "L" {
+                links.insert((fields[1].to_string(), fields[3].to_string()));
+            }
+        }
+        let mut spelled = String::new();
+        for (i, segment) in gaf_info.path.iter().enumerate() {
+            spelled.push_str(&segments[segment]);
+            if i > 0 {
+                assert!(links.contains(&(gaf_info.path[i - 1].clone(), segment.clone())));
+            }
+        }
+        assert_eq!(spelled.len(), gaf_info.path_len);
+        assert_eq!(
+            &spelled.as_bytes()[..gaf_info.path_end],
+            &read_bytes[gaf_info.qstart..gaf_info.qend]
+        );
+
+        let gaf_line = gaf_string(&gaf_info);
+        let fields: Vec<&str> = gaf_line.split('\t').collect();
+        assert_eq!(fields[6], gaf_info.path_len.to_string());
+        assert_eq!(fields[7], "0");
+        assert_eq!(fields[8], gaf_info.path_end.to_string());
+    }
+}
@@ -1,4 +1,7 @@
-use crate::data_structs::{Color, KmerNode};
+use crate::data_structs::{Color, GenomePath, KmerNode, SeqGraph};
+use debruijn::dna_string::DnaString;
+use debruijn::Kmer;
+use debruijn::Mer;
 use fxhash::FxHashMap;
 use fxhash::FxHashSet;
 use smallvec::SmallVec;
@@ -6,6 +9,7 @@ use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::hash::Hash;
+use std::io::Write;
 
 fn has_unique_elements<T>(iter: T) -> bool
 where
@@ -258,13 +262,15 @@ pub fn add_align_to_graph(
     forward_strand: bool,
     samp_freq: usize,
     circular: bool,
-) {
+) -> Vec<(u32, usize)> {
     let mut strand_aln_nodes;
-    //Reverse the array so that the orders correspond to the indices
-    //(orders are swapped during chaining procedure for rev comps)
+    //Reverse the array and swap the orders of the query seeds so that the
+    //orders correspond to the indices for rev comps
     if forward_strand == false {
         strand_aln_nodes = vec![];
-        for node in aln_nodes.into_iter().rev() {
+        let n = aln_nodes.len() as u32;
+        for mut node in aln_nodes.into_iter().rev() {
+            node.order = n - node.order - 1;
             strand_aln_nodes.push(node);
         }
     } else {
@@ -283,6 +289,8 @@ pub fn add_align_to_graph(
     //        anchors.push(anchors[0]);
     //    }
     let mut num_contains_dist_not = 0;
+    //Nodes walked by the new genome along with the genome position of each seed
+    let mut genome_path = vec![];
     for i in 0..anchors.len() - 1 {
         let ref_node_len = ref_nodes.len();
 
@@ -332,6 +340,10 @@ pub fn add_align_to_graph(
         }
         let r_adjacent = kmer1r.child_nodes.contains(&kmer2r.id);
 
+        if i == 0 {
+            genome_path.push((kmer1r.id, kmer1q.primary_base.unwrap() as usize));
+        }
+
         let kmer1rorder = kmer1r.order;
         kmer1r.color |= 1;
         kmer2r.color |= 1;
@@ -396,6 +408,7 @@ pub fn add_align_to_graph(
                     .child_edge_distance
                     .push((genome_dist_query_adj.0, (1, edge_id)));
             }
+            genome_path.push((kmer2r.id, kmer2q.primary_base.unwrap() as usize));
             continue;
         } else {
             //            dbg!(&kmer2q, &kmer1q);
@@ -424,7 +437,7 @@ pub fn add_align_to_graph(
                     order_val: 0,
                     kmer: strand_aln_nodes[i as usize].kmer,
                     child_nodes: SmallVec::<[u32; 1]>::new(),
-                    child_edge_distance: SmallVec::<[(u16, (Color, u8)); 1]>::new(),
+                    child_edge_distance: SmallVec::<[(u32, (Color, u8)); 1]>::new(),
                     color: 1,
                     //xnor hack. truth table is
                     //11 1
@@ -434,7 +447,8 @@ pub fn add_align_to_graph(
                     canonical: strand_aln_nodes[i as usize].canonical == forward_strand,
                     actual_ref_positions: SmallVec::<[usize; 0]>::new(),
                     repetitive: strand_aln_nodes[i as usize].repetitive,
-                    primary_base: None
+                    primary_base: None,
+                    span: strand_aln_nodes[i as usize].span,
                 };
 
                 let genome_dist_query;
@@ -455,6 +469,10 @@ pub fn add_align_to_graph(
                         .actual_ref_positions
                         .push(strand_aln_nodes[i as usize].actual_ref_positions[0]);
                 }
+                genome_path.push((
+                    new_id as u32,
+                    strand_aln_nodes[i as usize].primary_base.unwrap() as usize,
+                ));
                 parent_node.child_nodes.push(new_id as u32);
                 parent_node.child_edge_distance.push((
                     genome_dist_query.0,
@@ -465,6 +483,7 @@ pub fn add_align_to_graph(
                 parent_node = &mut new_nodes[nn_len - 1];
             }
             parent_node.child_nodes.push(kmer2r.id);
+            genome_path.push((kmer2r.id, kmer2q.primary_base.unwrap() as usize));
             let genome_dist_query;
             if forward_strand {
                 if kmer2q.order == 0 {
@@ -493,6 +512,267 @@ pub fn add_align_to_graph(
     for node in new_nodes {
         ref_nodes.push(node);
     }
+
+    return genome_path;
+}
+
+//Label every edge walked by a genome with the bases between its two seeds.
+//genome_paths[i] is the (node id, genome position) walk of chroms[i].
+pub fn label_edges(
+    ref_nodes: &Vec<KmerNode>,
+    genome_paths: &Vec<Vec<(u32, usize)>>,
+    chroms: &Vec<(DnaString, bool)>,
+    genome_gaps: &Vec<Vec<(usize, usize)>>,
+    k: usize,
+) -> SeqGraph {
+    let mut seq_graph = SeqGraph::default();
+    let mut pool_ids: FxHashMap<DnaString, u32> = FxHashMap::default();
+    let num_genomes = chroms.len();
+
+    for (i, path) in genome_paths.iter().enumerate() {
+        let (genome, strand) = &chroms[i];
+        let color: Color = 1 << (num_genomes - i - 1);
+        let len = genome.len();
+        if path.is_empty() {
+            seq_graph.genome_paths.push(GenomePath {
+                head: u32::MAX,
+                start: 0,
+                rest: genome.clone(),
+                strand: *strand,
+                gaps: genome_gaps[i].clone(),
+            });
+            continue;
+        }
+
+        //Work in graph orientation, so reverse genomes are reverse complemented
+        //and their seed positions flipped.
+        let oriented;
+        if *strand {
+            oriented = genome.clone();
+        } else {
+            oriented = genome.rc();
+        }
+        let mut positions = vec![];
+        for (_id, pos) in path.iter() {
+            if *strand {
+                positions.push(*pos);
+            } else {
+                positions.push(len - pos - k);
+            }
+        }
+        let mut oriented_gaps = vec![];
+        for (start, end) in genome_gaps[i].iter() {
+            if *strand {
+                oriented_gaps.push((*start, *end));
+            } else {
+                oriented_gaps.push((len - end, len - start));
+            }
+        }
+        oriented_gaps.sort();
+
+        for j in 0..path.len() - 1 {
+            let parent = path[j].0;
+            let child = path[j + 1].0;
+            let label = circular_slice(&oriented, positions[j], positions[j + 1]);
+            let pool_id;
+            if let Some(id) = pool_ids.get(&label) {
+                pool_id = *id;
+            } else {
+                pool_id = seq_graph.seq_pool.len() as u32;
+                pool_ids.insert(label.clone(), pool_id);
+                seq_graph.seq_pool.push(label);
+            }
+            if circular_overlaps(&oriented_gaps, positions[j], positions[j + 1], len) {
+                seq_graph.gap_labels.insert(pool_id);
+            }
+
+            let edge = seq_graph
+                .edge_seqs
+                .entry((ref_nodes[parent as usize].id, ref_nodes[child as usize].id))
+                .or_insert(SmallVec::new());
+            let mut found = false;
+            for (edge_color, edge_pool_id) in edge.iter_mut() {
+                if *edge_pool_id == pool_id {
+                    *edge_color |= color;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                edge.push((color, pool_id));
+            }
+        }
+
+        seq_graph.genome_paths.push(GenomePath {
+            head: path[0].0,
+            start: positions[0],
+            rest: circular_slice(&oriented, positions[positions.len() - 1], positions[0]),
+            strand: *strand,
+            gaps: genome_gaps[i].clone(),
+        });
+    }
+
+    println!(
+        "Labelled {} edges with {} distinct sequences, {} of them spanning gaps.",
+        seq_graph.edge_seqs.len(),
+        seq_graph.seq_pool.len(),
+        seq_graph.gap_labels.len()
+    );
+
+    return seq_graph;
+}
+
+//Whether any of the sorted intervals overlaps the bases from start to end,
+//wrapping around the end of the genome if end < start.
+fn circular_overlaps(intervals: &Vec<(usize, usize)>, start: usize, end: usize, len: usize) -> bool {
+    if end < start {
+        return circular_overlaps(intervals, start, len, len) || circular_overlaps(intervals, 0, end, len);
+    }
+    let next_interval = intervals.partition_point(|interval| interval.1 <= start);
+    return next_interval < intervals.len() && intervals[next_interval].0 < end;
+}
+
+//Bases from start to end, wrapping around the end of the genome if end < start.
+fn circular_slice(genome: &DnaString, start: usize, end: usize) -> DnaString {
+    let mut seq;
+    if end >= start {
+        seq = genome.slice(start, end).to_owned();
+    } else {
+        seq = genome.slice(start, genome.len()).to_owned();
+        seq.extend(genome.slice(0, end).iter());
+    }
+    return seq;
+}
+
+//Bases start to end of a genome as text. A DnaString holds A wherever the
+//genome had an N or other non-ACGT base, so the recorded gaps are written
+//back as N.
+pub fn genome_bytes(
+    genome: &DnaString,
+    gaps: &Vec<(usize, usize)>,
+    start: usize,
+    end: usize,
+) -> Vec<u8> {
+    let mut bytes = genome.slice(start, end).to_string().into_bytes();
+    let first_gap = gaps.partition_point(|gap| gap.1 <= start);
+    for (gap_start, gap_end) in gaps[first_gap..].iter() {
+        if *gap_start >= end {
+            break;
+        }
+        for i in usize::max(*gap_start, start)..usize::min(*gap_end, end) {
+            bytes[i - start] = b'N';
+        }
+    }
+    return bytes;
+}
+
+//Rebuild the genome of the ith colour (same index as chroms) as text by
+//walking the labelled edges of its colour, with its gaps as N.
+pub fn genome_from_graph(
+    ref_nodes: &Vec<KmerNode>,
+    seq_graph: &SeqGraph,
+    index: usize,
+) -> Vec<u8> {
+    let genome_path = &seq_graph.genome_paths[index];
+    let num_genomes = seq_graph.genome_paths.len();
+    let color: Color = 1 << (num_genomes - index - 1);
+    if genome_path.head == u32::MAX {
+        let len = genome_path.rest.len();
+        return genome_bytes(&genome_path.rest, &genome_path.gaps, 0, len);
+    }
+
+    let mut walked = DnaString::new();
+    let mut node = genome_path.head;
+    loop {
+        let mut next_node = None;
+        for child in ref_nodes[node as usize].child_nodes.iter() {
+            if let Some(edge) = seq_graph.edge_seqs.get(&(node, *child)) {
+                for (edge_color, pool_id) in edge.iter() {
+                    if edge_color & color != 0 {
+                        walked.extend(seq_graph.seq_pool[*pool_id as usize].iter());
+                        next_node = Some(*child);
+                        break;
+                    }
+                }
+            }
+            if next_node.is_some() {
+                break;
+            }
+        }
+        if let Some(child) = next_node {
+            node = child;
+        } else {
+            break;
+        }
+    }
+    walked.extend(genome_path.rest.iter());
+
+    //walked starts at the first seed, so rotate it back into place
+    let len = walked.len();
+    let mut oriented = walked.slice(len - genome_path.start, len).to_owned();
+    oriented.extend(walked.slice(0, len - genome_path.start).iter());
+    let genome;
+    if genome_path.strand {
+        genome = oriented;
+    } else {
+        genome = oriented.rc();
+    }
+    return genome_bytes(&genome, &genome_path.gaps, 0, len);
+}
+
+//GFA segment holding the label of one colour variant of an edge.
+pub fn edge_segment_name(parent: u32, child: u32, pool_id: u32) -> String {
+    return format!("{}_{}_{}", parent, child, pool_id);
+}
+
+//Write the labelled graph as GFA 1. Every edge variant is a segment named by
+//edge_segment_name holding its label, which runs from the start of the parent
+//minimizer to the start of the child one, so consecutive labels join without
+//overlap. Every node is a segment named by its id holding its minimizer in
+//graph orientation, linked after its incoming labels so a walk can end on the
+//last minimizer.
+pub fn write_gfa<W: Write>(ref_nodes: &Vec<KmerNode>, seq_graph: &SeqGraph, out: &mut W) {
+    writeln!(out, "H\tVN:Z:1.0").unwrap();
+    let mut edges: Vec<&(u32, u32)> = seq_graph.edge_seqs.keys().collect();
+    edges.sort();
+    let mut out_segments: FxHashMap<u32, Vec<String>> = FxHashMap::default();
+    for (parent, child) in edges.iter() {
+        for (_color, pool_id) in seq_graph.edge_seqs[&(*parent, *child)].iter() {
+            out_segments
+                .entry(*parent)
+                .or_insert(vec![])
+                .push(edge_segment_name(*parent, *child, *pool_id));
+        }
+    }
+
+    for node in ref_nodes.iter() {
+        let kmer;
+        if node.canonical {
+            kmer = node.kmer;
+        } else {
+            kmer = node.kmer.rc();
+        }
+        writeln!(out, "S\t{}\t{}", node.id, kmer.to_string()).unwrap();
+    }
+    for (parent, child) in edges.iter() {
+        for (color, pool_id) in seq_graph.edge_seqs[&(*parent, *child)].iter() {
+            let name = edge_segment_name(*parent, *child, *pool_id);
+            writeln!(
+                out,
+                "S\t{}\t{}\tCL:Z:{:#b}",
+                name,
+                seq_graph.seq_pool[*pool_id as usize].to_string(),
+                color
+            )
+            .unwrap();
+            writeln!(out, "L\t{}\t+\t{}\t+\t0M", name, child).unwrap();
+            if let Some(next_segments) = out_segments.get(child) {
+                for next in next_segments.iter() {
+                    writeln!(out, "L\t{}\t+\t{}\t+\t0M", name, next).unwrap();
+                }
+            }
+        }
+    }
 }
 
 pub fn get_closest_node(ref_nodes: &Vec<KmerNode>) -> Vec<Option<u32>> {
@@ -641,3 +921,25 @@ pub fn get_closest_node(ref_nodes: &Vec<KmerNode>) -> Vec<Option<u32>> {
     );
     return closest_nodes;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn genomes_round_trip_through_edge_labels() {
+        let mut genomes = test_utils::test_genomes(&test_utils::TEST_REFS, 200000);
+        //A reverse genome and an N run exercise both strands and gaps
+        genomes[2] = DnaString::from_acgt_bytes(&genomes[2]).rc().to_string().into_bytes();
+        for c in genomes[1][50000..50500].iter_mut() {
+            *c = b'N';
+        }
+        let graph = test_utils::build_graph(&genomes, &test_utils::minimizer_params());
+        assert!(!graph.chroms[2].1);
+        for i in 0..genomes.len() {
+            let rebuilt = genome_from_graph(&graph.nodes, &graph.seq_graph, i);
+            assert_eq!(rebuilt, genomes[i].to_ascii_uppercase());
+        }
+    }
+}
@@ -5,6 +5,12 @@ pub mod seeding_methods_bit;
 pub mod chain;
 pub mod data_structs;
 pub mod align;
+pub mod aligner;
+pub mod bam_output;
+pub mod graph_align;
 pub mod deconvolution;
 pub mod coord_chain;
+pub mod mapper;
 pub mod constants;
+#[cfg(test)]
+pub mod test_utils;
@@ -0,0 +1,602 @@
+use crate::align;
+use crate::aligner::{Aligner, BlockAligner};
+use crate::chain;
+use crate::constants;
+use crate::coord_chain;
+use crate::data_structs::{Color, KmerNode, Mapping, Params, ReadMapping, SeedParams, SeqGraph};
+use crate::graph_align;
+use crate::graph_utils;
+use crate::seeding_methods_bit;
+use debruijn::dna_string::DnaString;
+use debruijn::kmer::Kmer16;
+use fxhash::{FxHashMap, FxHashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::Instant;
+
+//Everything needed to map reads onto a graph from generate. align asks for a
+//base level alignment against the best colour's genome, graph_align for a GAF
+//alignment to the graph, surject for aligning against the genome carrying most
+//of the graph alignment, paf for chaining-only positions and recomb for colour
+//switching paths. aligner does the base level alignments. chrom_gaps are the
+//runs of Ns in each genome, which chroms holds as A.
+pub struct Mapper {
+    pub ref_graph: Vec<KmerNode>,
+    pub chroms: Vec<(DnaString, bool)>,
+    pub chrom_names: Vec<String>,
+    pub chrom_gaps: Vec<Vec<(usize, usize)>>,
+    pub dont_use_kmers: FxHashSet<Kmer16>,
+    pub seq_graph: SeqGraph,
+    pub seed_params: SeedParams,
+    pub frequent_kmers: FxHashMap<Kmer16, usize>,
+    pub order_to_id: Vec<u32>,
+    pub ref_hash_map: FxHashMap<Kmer16, Vec<u32>>,
+    pub params: Params,
+    pub circular: bool,
+    pub chain_heuristic: bool,
+    pub align: bool,
+    pub graph_align: bool,
+    pub paf: bool,
+    pub surject: bool,
+    pub recomb: bool,
+    pub aligner: Box<dyn Aligner>,
+}
+
+impl Mapper {
+    pub fn new(
+        mut ref_graph: Vec<KmerNode>,
+        mut chroms: Vec<(DnaString, bool)>,
+        chrom_names: Vec<String>,
+        dont_use_kmers: FxHashSet<Kmer16>,
+        seq_graph: SeqGraph,
+        seed_params: SeedParams,
+        frequent_kmers: FxHashMap<Kmer16, usize>,
+        params: Params,
+    ) -> Mapper {
+        seeding_methods_bit::check_seed_hash(&seed_params);
+
+        //Genomes weren't stored, rebuild them from the edge sequences
+        let now = Instant::now();
+        for i in 0..chroms.len() {
+            if chroms[i].0.is_empty() {
+                chroms[i].0 = DnaString::from_acgt_bytes(&graph_utils::genome_from_graph(
+                    &ref_graph, &seq_graph, i,
+                ));
+            }
+        }
+        println!("Genome rebuilding time: {}", now.elapsed().as_secs_f32());
+        let mut chrom_gaps = vec![];
+        for i in 0..chroms.len() {
+            if let Some(genome_path) = seq_graph.genome_paths.get(i) {
+                chrom_gaps.push(genome_path.gaps.clone());
+            } else {
+                chrom_gaps.push(vec![]);
+            }
+        }
+
+        let order_to_id = graph_utils::top_sort(&mut ref_graph);
+        let ref_hash_map = chain::get_kmer_dict(&ref_graph);
+        Mapper {
+            ref_graph: ref_graph,
+            chroms: chroms,
+            chrom_names: chrom_names,
+            chrom_gaps: chrom_gaps,
+            dont_use_kmers: dont_use_kmers,
+            seq_graph: seq_graph,
+            seed_params: seed_params,
+            frequent_kmers: frequent_kmers,
+            order_to_id: order_to_id,
+            ref_hash_map: ref_hash_map,
+            params: params,
+            circular: false,
+            chain_heuristic: true,
+            align: false,
+            graph_align: false,
+            paf: false,
+            surject: false,
+            recomb: false,
+            aligner: Box::new(BlockAligner),
+        }
+    }
+
+    //Loads the .bin file written by generate.
+    pub fn from_file(file_name: &str, params: Params) -> Result<Mapper, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(file_name)?);
+        let (ref_graph, chroms, chrom_names, dont_use_kmers, seq_graph, seed_params, frequent_kmers): (
+            Vec<KmerNode>,
+            Vec<(DnaString, bool)>,
+            Vec<String>,
+            FxHashSet<Kmer16>,
+            SeqGraph,
+            SeedParams,
+            FxHashMap<Kmer16, usize>,
+        ) = bincode::deserialize_from(reader)?;
+        println!("Seeding parameters from graph: {:?}", seed_params);
+        return Ok(Mapper::new(
+            ref_graph,
+            chroms,
+            chrom_names,
+            dont_use_kmers,
+            seq_graph,
+            seed_params,
+            frequent_kmers,
+            params,
+        ));
+    }
+
+    //Chains a read onto the graph, finds the best colours for every chain and
+    //aligns the best chains if asked to. seq may contain non-ACGT and lowercase
+    //bases, which are left unseeded as in generate.
+    pub fn map_read(&self, read_id: &str, seq: &[u8], quals: &[u8]) -> ReadMapping {
+        let total_time = Instant::now();
+        let time_stuff = true;
+        let params = &self.params;
+        let read = DnaString::from_acgt_bytes(seq);
+        let read_id = read_id.to_string();
+        let mut mappings = vec![];
+
+        println!("---------------Read: {}---------------", read_id);
+        let now = Instant::now();
+        let masked_intervals =
+            seeding_methods_bit::get_masked_intervals(seq, self.seed_params.soft_mask);
+        let read_seeds = seeding_methods_bit::seed_sequence(
+            &read,
+            &self.seed_params,
+            1,
+            &self.dont_use_kmers,
+            &self.frequent_kmers,
+            &masked_intervals,
+            false,
+        )
+        .0;
+        let masked_kmers = seeding_methods_bit::count_masked_seeds(
+            &read,
+            &self.seed_params,
+            &self.dont_use_kmers,
+            &self.frequent_kmers,
+            &masked_intervals,
+        );
+        if time_stuff {
+            println!("Seeding time: {}", now.elapsed().as_secs_f32());
+        }
+        println!("Left out {} seeds masked in the graph", masked_kmers);
+
+        //Homopolymer compression can leave short reads without seeds
+        if read_seeds.is_empty() {
+            println!("No seeds found for read");
+            return ReadMapping {
+                mappings: mappings,
+                masked_kmers: masked_kmers,
+                dropped_anchors: 0,
+            };
+        }
+        let q_hash_map = chain::get_kmer_dict(&read_seeds);
+
+        //Leave out k-mers with too many graph hits, counting the anchors they
+        //would have given
+        let mut skipped_kmers = FxHashSet::default();
+        let mut dropped_anchors = 0;
+        for (kmer, query_positions) in q_hash_map.iter() {
+            if let Some(ref_positions) = self.ref_hash_map.get(kmer) {
+                if ref_positions.len() > params.max_occ {
+                    skipped_kmers.insert(*kmer);
+                    dropped_anchors += ref_positions.len() * query_positions.len();
+                }
+            }
+        }
+        println!(
+            "Dropped {} anchors from {} repetitive k-mers",
+            dropped_anchors,
+            skipped_kmers.len()
+        );
+
+        let now = Instant::now();
+        let anc_score_strand_vec = chain::chain_seeds(
+            &self.ref_graph,
+            &read_seeds,
+            &self.ref_hash_map,
+            &q_hash_map,
+            params,
+            self.chain_heuristic,
+            true,
+            &skipped_kmers,
+            self.circular,
+            self.seed_params.method,
+        );
+        if time_stuff {
+            println!("Chaining time: {}", now.elapsed().as_secs_f32());
+        }
+
+        for (k, (best_anchors, score, read_strand)) in anc_score_strand_vec.into_iter().enumerate() {
+            let now = Instant::now();
+            let (best_colors, best_list_anchors) = chain::get_best_path_from_chain_rewrite(
+                &best_anchors,
+                &self.ref_graph,
+                &self.order_to_id,
+                &read_seeds,
+                read.len(),
+                false,
+                params,
+            );
+            if time_stuff {
+                println!("Path collection time: {}", now.elapsed().as_secs_f32());
+            }
+
+            let mut segments = vec![];
+            if self.recomb {
+                segments = chain::get_colour_switching_path(
+                    &best_anchors,
+                    &self.ref_graph,
+                    &read_seeds,
+                    self.chroms.len(),
+                    params,
+                );
+            }
+            mappings.push(Mapping {
+                chain: k,
+                strand: read_strand,
+                score: score,
+                anchors: best_anchors,
+                paths: best_colors
+                    .into_iter()
+                    .zip(best_list_anchors.into_iter())
+                    .map(|(color, (anchors, score))| (color, anchors, score))
+                    .collect(),
+                segments: segments,
+                primary_ref_chain: false,
+                aligned_color: None,
+                bam_info: vec![],
+                gaf_info: None,
+                paf_info: vec![],
+            });
+        }
+
+        if !self.align && !self.graph_align && !self.paf {
+            if time_stuff {
+                println!(
+                    "Total time mapping read {} is {}",
+                    read_id,
+                    total_time.elapsed().as_secs_f32()
+                );
+            }
+            return ReadMapping {
+                mappings: mappings,
+                masked_kmers: masked_kmers,
+                dropped_anchors: dropped_anchors,
+            };
+        }
+
+        //Chain on the first genome's coordinates if no good chain is found
+        let no_paths = mappings.iter().all(|x| x.paths.is_empty());
+        if no_paths {
+            if read.len() > params.read_length_super_chain_cutoff {
+                println!("No good alignment found; primary-ref-chaining");
+                let now = Instant::now();
+                let base_anc_score_strand_vec = coord_chain::get_base_chains(
+                    &self.ref_graph,
+                    &read_seeds,
+                    &self.ref_hash_map,
+                    &q_hash_map,
+                    params,
+                    &skipped_kmers,
+                    read.len(),
+                );
+                if time_stuff {
+                    println!("primary-ref chaining time: {}", now.elapsed().as_secs_f32());
+                }
+
+                for (k, (best_anchors, score, read_strand)) in
+                    base_anc_score_strand_vec.into_iter().enumerate()
+                {
+                    let now = Instant::now();
+                    let (best_colors, best_list_anchors) = chain::get_best_path_from_chain_rewrite(
+                        &best_anchors,
+                        &self.ref_graph,
+                        &self.order_to_id,
+                        &read_seeds,
+                        read.len(),
+                        true,
+                        params,
+                    );
+                    if time_stuff {
+                        println!("Path collection time: {}", now.elapsed().as_secs_f32());
+                    }
+                    mappings.push(Mapping {
+                        chain: k,
+                        strand: read_strand,
+                        score: score,
+                        anchors: best_anchors,
+                        paths: best_colors
+                            .into_iter()
+                            .zip(best_list_anchors.into_iter())
+                            .map(|(color, (anchors, score))| (color, anchors, score))
+                            .collect(),
+                        segments: vec![],
+                        primary_ref_chain: true,
+                        aligned_color: None,
+                        bam_info: vec![],
+                        gaf_info: None,
+                        paf_info: vec![],
+                    });
+                }
+            } else {
+                println!("No good alignment found");
+                return ReadMapping {
+                    mappings: mappings,
+                    masked_kmers: masked_kmers,
+                    dropped_anchors: dropped_anchors,
+                };
+            }
+        }
+
+        //Align the best path of every chain whose score is close to the best one
+        let now = Instant::now();
+        let mut best_paths = vec![];
+        for (i, mapping) in mappings.iter().enumerate() {
+            for (j, path) in mapping.paths.iter().enumerate() {
+                best_paths.push((i, j, path.2));
+            }
+        }
+        best_paths.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+        let top_n = 10;
+        let mut used_chains = FxHashSet::default();
+        let mut alignments = vec![];
+        let mut paf_lines = vec![];
+        let mut paf_genomes = FxHashSet::default();
+        for index in 0..usize::min(best_paths.len(), top_n) {
+            let (i, j, score) = best_paths[index];
+            let best_score = best_paths[0].2;
+            let chain_number = mappings[i].chain;
+            let anchors = &mappings[i].paths[j].1;
+            //Paths of a chain already aligned are reported in the PAF too
+            if !(best_score < score + self.params.secondary_score_margin
+                || (best_score - score)
+                    < self.params.secondary_anchor_fraction * (anchors.len() * constants::K) as f64)
+                && !used_chains.contains(&chain_number)
+            {
+                continue;
+            }
+            let mut color: Color = mappings[i].paths[j].0;
+            let read_strand = mappings[i].strand;
+            if !used_chains.contains(&chain_number) {
+                used_chains.insert(chain_number);
+
+                let mut gaf_info = None;
+                if self.graph_align {
+                    gaf_info = graph_align::align_to_graph(
+                        anchors,
+                        &self.ref_graph,
+                        &self.seq_graph,
+                        &read_seeds,
+                        &read,
+                        read_strand,
+                        &read_id,
+                        params,
+                    );
+                    if let Some(gaf_info) = &gaf_info {
+                        if self.surject {
+                            color = gaf_info.surject_color;
+                        }
+                    }
+                }
+
+                let mut bam_info = vec![];
+                if self.align {
+                    bam_info = align::align_from_chain(
+                        anchors,
+                        &self.chroms,
+                        &self.chrom_gaps,
+                        color,
+                        &self.ref_graph,
+                        &read_seeds,
+                        &read,
+                        seq,
+                        read_strand,
+                        quals,
+                        &self.chrom_names,
+                        &read_id,
+                        params,
+                        self.circular,
+                        &*self.aligner,
+                    );
+                }
+                alignments.push((i, color, gaf_info, bam_info));
+            }
+
+            //Every genome of every reported path gets PAF lines, not only the
+            //aligned one
+            if self.paf {
+                let mut second_score = 0.;
+                for (i2, _j2, score2) in best_paths.iter() {
+                    if *i2 != i {
+                        second_score = *score2;
+                        break;
+                    }
+                }
+                let mapq = align::chain_mapq(score, second_score, anchors.len());
+                let first_bit = align::get_first_nonzero_bit(color);
+                let mut bits = vec![first_bit];
+                for bit in align::get_nonzero_bits_fast(mappings[i].paths[j].0) {
+                    if bit != first_bit {
+                        bits.push(bit);
+                    }
+                }
+                for bit in bits {
+                    if !paf_genomes.insert((i, bit)) {
+                        continue;
+                    }
+                    let mut lines = align::paf_from_chain(
+                        anchors,
+                        &self.chroms,
+                        1 << bit,
+                        &self.ref_graph,
+                        &read_seeds,
+                        read.len(),
+                        read_strand,
+                        &self.chrom_names,
+                        &read_id,
+                        score,
+                        mapq,
+                        self.circular,
+                    );
+                    //Only the first genome of the best path is primary
+                    if !paf_lines.is_empty() {
+                        for record in lines.iter_mut() {
+                            record.primary = false;
+                        }
+                    }
+                    if !lines.is_empty() {
+                        paf_lines.push((i, lines));
+                    }
+                }
+            }
+        }
+        //Chains after the best one aligned are secondary alignments
+        for (k, (i, color, gaf_info, mut bam_info)) in alignments.into_iter().enumerate() {
+            if k > 0 {
+                align::mark_secondary(&mut bam_info);
+            }
+            mappings[i].aligned_color = Some(color);
+            mappings[i].gaf_info = gaf_info;
+            mappings[i].bam_info = bam_info;
+        }
+        for (i, lines) in paf_lines {
+            mappings[i].paf_info.extend(lines);
+        }
+        if time_stuff {
+            println!("Total align time {}", now.elapsed().as_secs_f32());
+            println!(
+                "Total time mapping read {} is {}",
+                read_id,
+                total_time.elapsed().as_secs_f32()
+            );
+        }
+        return ReadMapping {
+            mappings: mappings,
+            masked_kmers: masked_kmers,
+            dropped_anchors: dropped_anchors,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants;
+    use crate::test_utils;
+
+    fn test_mapper() -> Mapper {
+        let genomes = test_utils::test_genomes(&test_utils::TEST_REFS, 100_000);
+        let graph = test_utils::build_graph(&genomes, &test_utils::minimizer_params());
+        return Mapper::new(
+            graph.nodes,
+            graph.chroms,
+            vec![
+                "ash1".to_string(),
+                "chm13".to_string(),
+                "grch38".to_string(),
+            ],
+            graph.dont_use_kmers,
+            graph.seq_graph,
+            test_utils::minimizer_params(),
+            graph.frequent_kmers,
+            constants::default_params(),
+        );
+    }
+
+    //Seed k-mers of seq without a k-mer mask
+    fn unmasked_kmers(mapper: &Mapper, seq: &[u8]) -> Vec<Kmer16> {
+        let (seeds, _) = seeding_methods_bit::seed_sequence(
+            &DnaString::from_acgt_bytes(seq),
+            &mapper.seed_params,
+            1,
+            &FxHashSet::default(),
+            &mapper.frequent_kmers,
+            &vec![],
+            false,
+        );
+        return seeds.iter().map(|x| x.kmer).collect();
+    }
+
+    #[test]
+    fn masked_kmers_are_counted_with_and_without_mappings() {
+        let genomes = test_utils::test_genomes(&test_utils::TEST_REFS[..1], 100_000);
+        let read = &genomes[0][20_000..25_000];
+        let short_read = &genomes[0][60_000..60_200];
+        let mut mapper = test_mapper();
+        let read_kmers = unmasked_kmers(&mapper, read);
+        let short_read_kmers = unmasked_kmers(&mapper, short_read);
+        for kmer in read_kmers.iter().step_by(3) {
+            mapper.dont_use_kmers.insert(*kmer);
+        }
+        for kmer in short_read_kmers.iter() {
+            mapper.dont_use_kmers.insert(*kmer);
+        }
+
+        let read_mapping = mapper.map_read("read", read, &vec![b'I'; read.len()]);
+        let masked = read_kmers
+            .iter()
+            .filter(|x| mapper.dont_use_kmers.contains(x))
+            .count();
+        assert!(masked > 0);
+        assert_eq!(read_mapping.masked_kmers, masked);
+        assert!(!read_mapping.mappings.is_empty());
+
+        //Every seed is masked, so there is nothing to chain
+        let read_mapping = mapper.map_read("short_read", short_read, &vec![b'I'; short_read.len()]);
+        assert!(read_mapping.mappings.is_empty());
+        assert!(short_read_kmers.len() > 0);
+        assert_eq!(read_mapping.masked_kmers, short_read_kmers.len());
+    }
+
+    #[test]
+    fn paf_lines_for_every_genome_of_the_reported_paths() {
+        let genomes = test_utils::test_genomes(&test_utils::TEST_REFS, 100_000);
+        let read = test_utils::mutate(&genomes[1][30_000..35_000], 0.02, 1);
+        let mut mapper = test_mapper();
+        mapper.paf = true;
+        let mappings = mapper.map_read("read", &read, &vec![b'I'; read.len()]).mappings;
+
+        let mut best_score = 0.;
+        for mapping in mappings.iter() {
+            for path in mapping.paths.iter() {
+                best_score = f64::max(best_score, path.2);
+            }
+        }
+        //All paths of a chain are reported once one of them is close to the best
+        let mut expected = FxHashSet::default();
+        for mapping in mappings.iter() {
+            let reported = mapping.paths.iter().any(|path| {
+                best_score < path.2 + mapper.params.secondary_score_margin
+                    || (best_score - path.2)
+                        < mapper.params.secondary_anchor_fraction * (path.1.len() * constants::K) as f64
+            });
+            if !reported {
+                continue;
+            }
+            for path in mapping.paths.iter() {
+                for bit in align::get_nonzero_bits_fast(path.0) {
+                    expected.insert(mapper.chrom_names[mapper.chroms.len() - bit - 1].clone());
+                }
+            }
+        }
+        assert!(expected.len() > 1);
+
+        let mut names = FxHashSet::default();
+        let mut primary = vec![];
+        for mapping in mappings.iter() {
+            for record in mapping.paf_info.iter() {
+                names.insert(record.tname.clone());
+                if record.primary {
+                    let aligned_bit = align::get_first_nonzero_bit(mapping.aligned_color.unwrap());
+                    let aligned_name = &mapper.chrom_names[mapper.chroms.len() - aligned_bit - 1];
+                    assert_eq!(&record.tname, aligned_name);
+                    primary.push(record.tname.clone());
+                }
+            }
+        }
+        assert_eq!(names, expected);
+        assert_eq!(primary.len(), 1);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::data_structs::{Color, KmerNode};
+use crate::constants;
+use crate::data_structs::{Color, KmerNode, SeedMethod, SeedParams};
 use debruijn::dna_string::*;
 use debruijn::kmer::Kmer10;
 use debruijn::kmer::Kmer12;
@@ -7,43 +8,87 @@ use debruijn::kmer::Kmer8;
 use debruijn::Mer;
 use debruijn::Vmer;
 use debruijn::*;
-use fnv::FnvHasher;
-use fxhash::hash;
 use fxhash::{FxHashMap, FxHashSet};
 use smallvec::SmallVec;
+use std::collections::VecDeque;
 use std::fs::File;
-use std::hash::{Hash as _Hash, Hasher as _Hasher};
 use std::io::{BufRead, BufReader};
 
+//Running k-mer counts over the genomes: total copies, number of genomes the
+//k-mer is in and the last genome it was seen in.
+type KmerTotals = FxHashMap<Kmer16, (usize, usize, usize)>;
+
+fn add_kmer(totals: &mut KmerTotals, kmer: Kmer16, genome_index: usize) {
+    let total = totals.entry(kmer).or_insert((0, 0, usize::MAX));
+    total.0 += 1;
+    if total.2 != genome_index {
+        total.1 += 1;
+        total.2 = genome_index;
+    }
+}
+
+//Copy number of every k-mer averaged over the genomes that contain it, so a
+//k-mer found once in each genome is still unique.
+fn average_copy_numbers(totals: KmerTotals) -> FxHashMap<Kmer16, usize> {
+    return totals
+        .into_iter()
+        .map(|(kmer, (count, num_genomes, _))| (kmer, (count + num_genomes / 2) / num_genomes))
+        .collect();
+}
+
+//Built in replacement for the marbl weighting file. Every canonical k-mer of
+//every genome is counted and the ones with a copy number of at least
+//FREQUENT_KMER_MIN_COUNT are down-weighted when picking minimizers.
+pub fn get_frequent_kmers(
+    genomes: &Vec<(DnaString, bool)>,
+    seed_params: &SeedParams,
+) -> FxHashMap<Kmer16, usize> {
+    let mut totals = KmerTotals::default();
+    for (i, (genome, _)) in genomes.iter().enumerate() {
+        let compressed;
+        let genome = if seed_params.hpc {
+            compressed = homopolymer_compress(genome).0;
+            &compressed
+        } else {
+            genome
+        };
+        if genome.len() >= 16 {
+            for kmer in genome.iter_kmers::<Kmer16>() {
+                add_kmer(&mut totals, kmer.min_rc(), i);
+            }
+        }
+    }
+    let mut frequent_kmers = average_copy_numbers(totals);
+    frequent_kmers.retain(|_, count| *count >= constants::FREQUENT_KMER_MIN_COUNT);
+    return frequent_kmers;
+}
+
 pub fn get_masked_kmers(
-    s: &DnaString,
-    w: usize,
-    k: usize,
-    s_sync: usize,
-    t: usize,
+    genomes: &Vec<(DnaString, bool)>,
+    genome_masks: &Vec<Vec<(usize, usize)>>,
+    seed_params: &SeedParams,
     fraction_mask_f64: f64,
-    use_minimizers: bool,
     frequent_kmers: &FxHashMap<Kmer16, usize>,
 ) -> FxHashSet<Kmer16> {
     //Get the discarded k-mers here and don't use these k-mers when seeding
-    let seeds1;
-    if use_minimizers {
-        let (seeds, _p1) =
-            minimizer_seeds(s, w, k, 100, &FxHashSet::default(), frequent_kmers, false);
-        seeds1 = seeds;
-    } else {
-        let (seeds, _p1) = 
-            open_sync_seeds(s, k, t, s_sync, 100, &FxHashSet::default(), frequent_kmers, false);
-        seeds1 = seeds;
-    }
-    let mut kmer_count_dict = FxHashMap::default();
-    for node in seeds1.into_iter() {
-        let count_num = kmer_count_dict.entry(node.kmer).or_insert(0);
-        *count_num += 1;
-    }
-
-    let mut hash_vec: Vec<(Kmer16, usize)> = kmer_count_dict.into_iter().collect();
-    hash_vec.sort_by(|a, b| b.1.cmp(&a.1));
+    let mut totals = KmerTotals::default();
+    for (i, (genome, _)) in genomes.iter().enumerate() {
+        let (seeds, _p) = seed_sequence(
+            genome,
+            seed_params,
+            100,
+            &FxHashSet::default(),
+            frequent_kmers,
+            &genome_masks[i],
+            false,
+        );
+        for node in seeds.into_iter() {
+            add_kmer(&mut totals, node.kmer, i);
+        }
+    }
+
+    let mut hash_vec: Vec<(Kmer16, usize)> = average_copy_numbers(totals).into_iter().collect();
+    hash_vec.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
     let mut dont_use_kmers = FxHashSet::default();
 
     for i in 0..(hash_vec.len() as f64 * fraction_mask_f64) as usize {
@@ -51,6 +96,37 @@ pub fn get_masked_kmers(
     }
     return dont_use_kmers;
 }
+//Thomas Wang's 64-bit integer hash restricted to the low bits in mask, as used
+//by minimap2. It is a bijection on [0, mask], so distinct k-mers never collide,
+//and it only uses fixed width integer arithmetic so every platform and build
+//orders k-mers the same way. Graphs record constants::SEED_HASH and reads
+//must be seeded with the same hash.
+pub fn hash64(key: u64, mask: u64) -> u64 {
+    let mut key = (!key).wrapping_add(key << 21) & mask;
+    key = key ^ (key >> 24);
+    key = (key.wrapping_add(key << 3)).wrapping_add(key << 8) & mask;
+    key = key ^ (key >> 14);
+    key = (key.wrapping_add(key << 2)).wrapping_add(key << 4) & mask;
+    key = key ^ (key >> 28);
+    key = key.wrapping_add(key << 31) & mask;
+    return key;
+}
+
+//Seeds from a different hash would not match the graph's seeds.
+pub fn check_seed_hash(seed_params: &SeedParams) {
+    if seed_params.seed_hash != constants::SEED_HASH {
+        panic!(
+            "Graph was seeded with k-mer hash {} but this version seeds with {}; regenerate the graph.",
+            seed_params.seed_hash,
+            constants::SEED_HASH
+        );
+    }
+}
+
+fn kmer_hash<K: Kmer>(kmer: &K) -> u64 {
+    hash64(kmer.to_u64(), (1u64 << (2 * K::k())) - 1)
+}
+
 fn position_min<T: Ord>(slice: &[T]) -> Option<usize> {
     slice
         .iter()
@@ -68,18 +144,217 @@ pub fn minimizer_seeds(
     frequent_kmers: &FxHashMap<Kmer16, usize>,
     primary_reference: bool,
 ) -> (Vec<KmerNode>, Vec<u32>) {
-    let use_fnv = false;
+    let mut minimizer_seeds: Vec<KmerNode> = vec![];
+    let mut positions_selected: Vec<u32> = Vec::new();
+
+    //Window minimum from a deque of (hash, position) with hashes increasing
+    //from the front. Equal hashes are all kept so ties are broken the way the
+    //ring buffer rescan this replaces broke them: k-mers before the first full
+    //window count as hash 0, a k-mer tying the minimum only takes over when
+    //the minimum leaves the window, and otherwise the tied k-mer whose ring
+    //buffer slot (pos + 1) % w is largest wins.
+    let mut window: VecDeque<(u64, usize)> = VecDeque::with_capacity(w + 1);
+    let mut min_pos = usize::MAX;
+    let mut min_hash = 0;
+    if s.len() < k + 1 {
+        return (vec![], vec![]);
+    }
+    let mut num_samp_coord = 0;
+
+    //Roll the forward and reverse complement k-mers along the string instead
+    //of slicing every k-mer out of it
+    let kmer_mask = (1u64 << (2 * k)) - 1;
+    let mut fwd_bits = 0u64;
+    let mut rc_bits = 0u64;
+    for (j, base) in s.iter().enumerate() {
+        fwd_bits = ((fwd_bits << 2) | base as u64) & kmer_mask;
+        rc_bits = (rc_bits >> 2) | (((3 - base) as u64) << (2 * (k - 1)));
+        if j + 1 < k {
+            continue;
+        }
+        let i = j + 1 - k;
+        let kmer = Kmer16::from_u64(fwd_bits);
+        let rc_kmer = Kmer16::from_u64(rc_bits);
+        let hash_kmer;
+        if kmer < rc_kmer {
+            hash_kmer = kmer;
+        } else {
+            hash_kmer = rc_kmer;
+        }
+        let mut hash_val = kmer_hash(&hash_kmer);
+        //winnowmap weighting test TODO
+        if frequent_kmers.contains_key(&hash_kmer) {
+            let num_iters = (frequent_kmers[&hash_kmer] as f64).log(2.0);
+            for _i in 0..num_iters as usize {
+                hash_val += (u64::MAX - hash_val) / 2;
+            }
+        }
+        if i < w - 1 {
+            hash_val = 0;
+        }
+        while let Some(back) = window.back() {
+            if back.0 > hash_val {
+                window.pop_back();
+            } else {
+                break;
+            }
+        }
+        window.push_back((hash_val, i));
+        if i < w - 1 {
+            continue;
+        }
+        while window.front().unwrap().1 + w <= i {
+            window.pop_front();
+        }
+
+        if min_pos == usize::MAX || min_pos + w <= i {
+            let front_hash = window.front().unwrap().0;
+            if min_pos != usize::MAX && hash_val == front_hash {
+                min_pos = i;
+            } else {
+                min_pos = window.front().unwrap().1;
+                for (tied_hash, tied_pos) in window.iter() {
+                    if *tied_hash != front_hash {
+                        break;
+                    }
+                    if (tied_pos + 1) % w > (min_pos + 1) % w {
+                        min_pos = *tied_pos;
+                    }
+                }
+            }
+            min_hash = front_hash;
+        } else if hash_val < min_hash {
+            min_pos = i;
+            min_hash = hash_val;
+        } else {
+            continue;
+        }
+
+        let kmer: Kmer16 = s.slice(min_pos, min_pos + k).get_kmer(0);
+        let canonical;
+        let mut node_kmer = kmer.rc();
+        if node_kmer < kmer {
+            canonical = false;
+        } else {
+            canonical = true;
+            node_kmer = kmer;
+        }
+
+        let mut distance_from_last = 0;
+        let mut distance_from_start = 0;
+        let mut sample_coord = false;
+        if !positions_selected.last().is_none() {
+            distance_from_last = min_pos as u32 - *positions_selected.last().unwrap() as u32;
+            distance_from_start = min_pos;
+        }
+
+        if distance_from_last as usize > 500 {
+            sample_coord = true;
+            //            ignore_mask = true;
+            //            dbg!(distance_from_last);
+        }
+        if !dont_use_kmers.contains(&node_kmer) || (sample_coord && primary_reference) {
+            if sample_coord {
+                num_samp_coord += 1;
+            }
+            positions_selected.push(min_pos as u32);
+            let mut kmer_node = KmerNode {
+                kmer: node_kmer,
+                id: positions_selected.len() as u32 - 1,
+                order: positions_selected.len() as u32 - 1,
+                order_val: distance_from_start as u32,
+                color: 1,
+                child_nodes: SmallVec::<[u32; 1]>::new(),
+                child_edge_distance: SmallVec::<[(u32, (Color, u8)); 1]>::new(),
+                //            child_nodes: vec![],
+                canonical: canonical,
+                actual_ref_positions: SmallVec::<[usize; 0]>::new(),
+                repetitive: sample_coord,
+                primary_base: Some(*positions_selected.last().unwrap() as u32),
+                span: k as u16,
+            };
+            if positions_selected.len() % samp_freq == 0 || sample_coord {
+                kmer_node
+                    .actual_ref_positions
+                    .push(*positions_selected.last().unwrap() as usize);
+            }
+
+            //Repetitive causes far spaced k-mers, make sure to index pairs
+            //that are far apart
+            if sample_coord {
+                minimizer_seeds.last_mut().unwrap().repetitive = true;
+                if minimizer_seeds
+                    .last()
+                    .unwrap()
+                    .actual_ref_positions
+                    .is_empty()
+                {
+                    minimizer_seeds
+                        .last_mut()
+                        .unwrap()
+                        .actual_ref_positions
+                        .push(positions_selected[positions_selected.len() - 2] as usize);
+                }
+            }
+
+            minimizer_seeds.push(kmer_node);
+        }
+        //        let pos_vec = minimizer_seeds
+        //            .entry(kmer)
+        //            .or_insert(FxHashSet::default());
+        //        pos_vec.insert(i-offset);
+    }
+
+    for i in 0..minimizer_seeds.len() {
+        if i == minimizer_seeds.len() - 1 {
+            minimizer_seeds[i].child_nodes.push(0 as u32);
+            //TODO this is incorrect -- why isthis incorrect??
+            let dist_on_genome = positions_selected[0] + s.len() as u32 - positions_selected[i];
+            minimizer_seeds[i]
+                .child_edge_distance
+                .push((dist_on_genome, (1, 0)));
+        } else {
+            minimizer_seeds[i].child_nodes.push((i + 1) as u32);
+            let dist_on_genome = positions_selected[i + 1] - positions_selected[i];
+            minimizer_seeds[i]
+                .child_edge_distance
+                .push((dist_on_genome, (1, 0)));
+        }
+    }
+
+    //TODO
+    //    if positions_selected.len() < 10000{
+    //        dbg!(&positions_selected);
+    //    }
+    //    dbg!(num_samp_coord);
+    return (minimizer_seeds, positions_selected);
+}
+
+//minimizer_seeds as it was before the rolling k-mers and the deque: every k-mer
+//is sliced out of the string and a ring buffer of the window's hashes is
+//rescanned whenever the minimum leaves it. Kept as the reference for the
+//seeds and the speed of minimizer_seeds in tests and benches/seeding.rs.
+pub fn sliced_minimizer_seeds(
+    s: &DnaString,
+    w: usize,
+    k: usize,
+    samp_freq: usize,
+    dont_use_kmers: &FxHashSet<Kmer16>,
+    frequent_kmers: &FxHashMap<Kmer16, usize>,
+    primary_reference: bool,
+) -> (Vec<KmerNode>, Vec<u32>) {
     let mut minimizer_seeds: Vec<KmerNode> = vec![];
     let mut positions_selected: Vec<u32> = Vec::new();
 
     //look at windows
     let mut running_pos = 0;
     let mut min_running_pos = usize::MAX;
-    let mut window_hashes = vec![0; w];
+    let mut window_hashes: Vec<u64> = vec![0; w];
     if s.len() < k + 1 {
         return (vec![], vec![]);
     }
     let mut num_samp_coord = 0;
+
     for i in 0..s.len() - k + 1 {
         let kmer: Kmer16 = s.slice(i, i + k).get_kmer(0);
         let rc_kmer = kmer.rc();
@@ -89,19 +364,13 @@ pub fn minimizer_seeds(
         } else {
             hash_kmer = rc_kmer;
         }
-        if use_fnv {
-            let mut state = FnvHasher::default();
-            hash_kmer.hash(&mut state);
-            window_hashes[running_pos] = state.finish() as usize;
-        } else {
-            window_hashes[running_pos] = hash(&hash_kmer);
-        }
+        window_hashes[running_pos] = kmer_hash(&hash_kmer);
         //winnowmap weighting test TODO
         if frequent_kmers.contains_key(&hash_kmer) {
             let mut hash_val = window_hashes[running_pos];
             let num_iters = (frequent_kmers[&hash_kmer] as f64).log(2.0);
             for _i in 0..num_iters as usize {
-                hash_val += (usize::MAX - hash_val) / 2;
+                hash_val += (u64::MAX - hash_val) / 2;
             }
             window_hashes[running_pos] = hash_val;
         }
@@ -170,12 +439,13 @@ pub fn minimizer_seeds(
                 order_val: distance_from_start as u32,
                 color: 1,
                 child_nodes: SmallVec::<[u32; 1]>::new(),
-                child_edge_distance: SmallVec::<[(u16, (Color, u8)); 1]>::new(),
+                child_edge_distance: SmallVec::<[(u32, (Color, u8)); 1]>::new(),
                 //            child_nodes: vec![],
                 canonical: canonical,
                 actual_ref_positions: SmallVec::<[usize; 0]>::new(),
                 repetitive: sample_coord,
                 primary_base: Some(*positions_selected.last().unwrap() as u32),
+                span: k as u16,
             };
             if positions_selected.len() % samp_freq == 0 || sample_coord {
                 kmer_node
@@ -219,13 +489,13 @@ pub fn minimizer_seeds(
             let dist_on_genome = positions_selected[0] + s.len() as u32 - positions_selected[i];
             minimizer_seeds[i]
                 .child_edge_distance
-                .push((dist_on_genome as u16, (1, 0)));
+                .push((dist_on_genome, (1, 0)));
         } else {
             minimizer_seeds[i].child_nodes.push((i + 1) as u32);
             let dist_on_genome = positions_selected[i + 1] - positions_selected[i];
             minimizer_seeds[i]
                 .child_edge_distance
-                .push((dist_on_genome as u16, (1, 0)));
+                .push((dist_on_genome, (1, 0)));
         }
     }
 
@@ -237,6 +507,307 @@ pub fn minimizer_seeds(
     return (minimizer_seeds, positions_selected);
 }
 
+//Collapse every homopolymer run to one base. The second vector holds the
+//position in the original string of each compressed base.
+pub fn homopolymer_compress(string: &DnaString) -> (DnaString, Vec<u32>) {
+    let mut compressed = DnaString::new();
+    let mut run_starts = vec![];
+    let mut last_base = u8::MAX;
+    for (i, base) in string.iter().enumerate() {
+        if base != last_base {
+            compressed.push(base);
+            run_starts.push(i as u32);
+            last_base = base;
+        }
+    }
+    return (compressed, run_starts);
+}
+
+//Move seeds found on the compressed string back to base positions. A seed
+//covers more than k bases when it contains a run, so its position is put in
+//the middle of the bases it covers like a strobemer; see strobemer_seeds.
+//Seeds covering more bases than a span can hold are dropped, as are seeds
+//whose middle rounds onto the previous seed's base.
+fn uncompress_seeds(
+    seeds: &mut Vec<KmerNode>,
+    positions: &mut Vec<u32>,
+    run_starts: &Vec<u32>,
+    len: usize,
+    k: usize,
+) {
+    if seeds.is_empty() {
+        return;
+    }
+    let wraps = seeds.last().unwrap().child_nodes.contains(&0);
+
+    let mut kept_seeds = vec![];
+    let mut kept_positions: Vec<u32> = vec![];
+    for (mut seed, cpos) in seeds.drain(..).zip(positions.drain(..)) {
+        let cpos = cpos as usize;
+        let start = run_starts[cpos] as usize;
+        let end;
+        if cpos + k < run_starts.len() {
+            end = run_starts[cpos + k] as usize;
+        } else {
+            end = len;
+        }
+        let span = end - start;
+        if span > u16::MAX as usize {
+            continue;
+        }
+        let pos;
+        if seed.canonical {
+            pos = start + (span - k) / 2;
+        } else {
+            pos = start + (span - k + 1) / 2;
+        }
+        if let Some(last_pos) = kept_positions.last() {
+            if pos <= *last_pos as usize {
+                continue;
+            }
+        }
+        seed.order_val = pos as u32;
+        seed.primary_base = Some(pos as u32);
+        seed.span = span as u16;
+        for ref_pos in seed.actual_ref_positions.iter_mut() {
+            *ref_pos = pos;
+        }
+        kept_seeds.push(seed);
+        kept_positions.push(pos as u32);
+    }
+
+    relink_seeds(&mut kept_seeds, &kept_positions, wraps, len);
+    *seeds = kept_seeds;
+    *positions = kept_positions;
+}
+
+//Renumber seeds left after dropping some and link them into a path, back to
+//the first seed if wraps.
+fn relink_seeds(seeds: &mut Vec<KmerNode>, positions: &Vec<u32>, wraps: bool, len: usize) {
+    for i in 0..seeds.len() {
+        let seed = &mut seeds[i];
+        seed.id = i as u32;
+        seed.order = i as u32;
+        seed.child_nodes.clear();
+        seed.child_edge_distance.clear();
+    }
+    for i in 0..seeds.len() {
+        if i == seeds.len() - 1 {
+            if wraps {
+                let dist_on_genome = positions[0] + len as u32 - positions[i];
+                seeds[i].child_nodes.push(0);
+                seeds[i]
+                    .child_edge_distance
+                    .push((dist_on_genome, (1, 0)));
+            }
+        } else {
+            let dist_on_genome = positions[i + 1] - positions[i];
+            seeds[i].child_nodes.push((i + 1) as u32);
+            seeds[i]
+                .child_edge_distance
+                .push((dist_on_genome, (1, 0)));
+        }
+    }
+}
+
+//Half open intervals of bases that should not be seeded: anything that is not
+//ACGT, which from_acgt_bytes would otherwise turn into A, and lowercase
+//soft-masked bases if soft_mask is set.
+pub fn get_masked_intervals(seq: &[u8], soft_mask: bool) -> Vec<(usize, usize)> {
+    let mut intervals: Vec<(usize, usize)> = vec![];
+    for (i, c) in seq.iter().enumerate() {
+        let masked;
+        match c {
+            b'A' | b'C' | b'G' | b'T' => masked = false,
+            b'a' | b'c' | b'g' | b't' => masked = soft_mask,
+            _ => masked = true,
+        }
+        if !masked {
+            continue;
+        }
+        if let Some(last) = intervals.last_mut() {
+            if last.1 == i {
+                last.1 = i + 1;
+                continue;
+            }
+        }
+        intervals.push((i, i + 1));
+    }
+    return intervals;
+}
+
+//Seed with whichever method the parameters ask for. The pieces of the string
+//between masked intervals are seeded on their own, so no k-mer or window
+//reaches into masked bases and the seeds of each piece are the ones it would
+//get as a separate sequence. The seeds of all pieces are then linked into one
+//path.
+pub fn seed_sequence(
+    string: &DnaString,
+    seed_params: &SeedParams,
+    samp_freq: usize,
+    dont_use_kmers: &FxHashSet<Kmer16>,
+    frequent_kmers: &FxHashMap<Kmer16, usize>,
+    masked: &Vec<(usize, usize)>,
+    is_primary: bool,
+) -> (Vec<KmerNode>, Vec<u32>) {
+    if masked.is_empty() {
+        return seed_unmasked(
+            string,
+            seed_params,
+            samp_freq,
+            dont_use_kmers,
+            frequent_kmers,
+            is_primary,
+        );
+    }
+
+    let mut seeds = vec![];
+    let mut positions = vec![];
+    let mut wraps = false;
+    let mut piece_start = 0;
+    for i in 0..masked.len() + 1 {
+        let piece_end;
+        if i < masked.len() {
+            piece_end = masked[i].0;
+        } else {
+            piece_end = string.len();
+        }
+        if piece_end > piece_start {
+            let (piece_seeds, piece_positions) = seed_unmasked(
+                &string.slice(piece_start, piece_end).to_owned(),
+                seed_params,
+                samp_freq,
+                dont_use_kmers,
+                frequent_kmers,
+                is_primary,
+            );
+            if let Some(last) = piece_seeds.last() {
+                wraps |= last.child_nodes.contains(&0);
+            }
+            for (mut seed, pos) in piece_seeds.into_iter().zip(piece_positions.into_iter()) {
+                seed.order_val += piece_start as u32;
+                seed.primary_base = seed.primary_base.map(|base| base + piece_start as u32);
+                for ref_pos in seed.actual_ref_positions.iter_mut() {
+                    *ref_pos += piece_start;
+                }
+                seeds.push(seed);
+                positions.push(pos + piece_start as u32);
+            }
+        }
+        if i < masked.len() {
+            piece_start = masked[i].1;
+        }
+    }
+    relink_seeds(&mut seeds, &positions, wraps, string.len());
+    return (seeds, positions);
+}
+
+//Number of seeds of the string that dont_use_kmers leaves out, i.e. the seeds
+//found without a k-mer mask whose k-mer is masked.
+pub fn count_masked_seeds(
+    string: &DnaString,
+    seed_params: &SeedParams,
+    dont_use_kmers: &FxHashSet<Kmer16>,
+    frequent_kmers: &FxHashMap<Kmer16, usize>,
+    masked: &Vec<(usize, usize)>,
+) -> usize {
+    if dont_use_kmers.is_empty() {
+        return 0;
+    }
+    let (seeds, _) = seed_sequence(
+        string,
+        seed_params,
+        1,
+        &FxHashSet::default(),
+        frequent_kmers,
+        masked,
+        false,
+    );
+    return seeds
+        .iter()
+        .filter(|x| dont_use_kmers.contains(&x.kmer))
+        .count();
+}
+
+fn seed_unmasked(
+    string: &DnaString,
+    seed_params: &SeedParams,
+    samp_freq: usize,
+    dont_use_kmers: &FxHashSet<Kmer16>,
+    frequent_kmers: &FxHashMap<Kmer16, usize>,
+    is_primary: bool,
+) -> (Vec<KmerNode>, Vec<u32>) {
+    if seed_params.hpc {
+        assert!(
+            seed_params.method != SeedMethod::Strobemer,
+            "homopolymer compression is not supported with strobemers"
+        );
+        let (compressed, run_starts) = homopolymer_compress(string);
+        let mut compressed_params = seed_params.clone();
+        compressed_params.hpc = false;
+        let (mut seeds, mut positions) = seed_unmasked(
+            &compressed,
+            &compressed_params,
+            samp_freq,
+            dont_use_kmers,
+            frequent_kmers,
+            is_primary,
+        );
+        uncompress_seeds(
+            &mut seeds,
+            &mut positions,
+            &run_starts,
+            string.len(),
+            seed_params.k,
+        );
+        return (seeds, positions);
+    }
+    match seed_params.method {
+        SeedMethod::Minimizer => minimizer_seeds(
+            string,
+            seed_params.w,
+            seed_params.k,
+            samp_freq,
+            dont_use_kmers,
+            frequent_kmers,
+            is_primary,
+        ),
+        SeedMethod::OpenSyncmer => sync_seeds(
+            string,
+            seed_params.k,
+            seed_params.t,
+            seed_params.s,
+            false,
+            samp_freq,
+            dont_use_kmers,
+            frequent_kmers,
+            is_primary,
+        ),
+        SeedMethod::ClosedSyncmer => sync_seeds(
+            string,
+            seed_params.k,
+            seed_params.t,
+            seed_params.s,
+            true,
+            samp_freq,
+            dont_use_kmers,
+            frequent_kmers,
+            is_primary,
+        ),
+        SeedMethod::Strobemer => strobemer_seeds(
+            string,
+            seed_params.w,
+            seed_params.k,
+            seed_params.strobe_w_min,
+            seed_params.strobe_w_max,
+            samp_freq,
+            dont_use_kmers,
+            frequent_kmers,
+            is_primary,
+        ),
+    }
+}
+
 pub fn open_sync_seeds(
     string: &DnaString,
     k: usize,
@@ -247,6 +818,34 @@ pub fn open_sync_seeds(
     frequent_kmers: &FxHashMap<Kmer16, usize>,
     is_primary: bool,
 ) -> (Vec<KmerNode>, Vec<u32>) {
+    return sync_seeds(
+        string,
+        k,
+        t,
+        s,
+        false,
+        samp_freq,
+        dont_use_kmers,
+        frequent_kmers,
+        is_primary,
+    );
+}
+
+//Open syncmers take a k-mer when its smallest s-mer is at offset t - 1 from
+//the end of the k-mer, closed syncmers when it is at either end.
+pub fn sync_seeds(
+    string: &DnaString,
+    k: usize,
+    t: usize,
+    s: usize,
+    closed: bool,
+    samp_freq: usize,
+    dont_use_kmers: &FxHashSet<Kmer16>,
+    frequent_kmers: &FxHashMap<Kmer16, usize>,
+    is_primary: bool,
+) -> (Vec<KmerNode>, Vec<u32>) {
+    assert!(s > 0 && s <= k, "s must be between 1 and k");
+    assert!(closed || (t > 0 && t <= k - s + 1), "t must be between 1 and k - s + 1");
     let mut syncmer_seeds = vec![];
     let mut positions_selected: Vec<u32> = Vec::new();
     //hash all s-mers
@@ -254,27 +853,28 @@ pub fn open_sync_seeds(
     let w = k - s + 1;
     let mut running_pos = 0;
     let mut min_running_pos = usize::MAX;
-    let mut window_hashes = vec![0; w];
+    let mut window_hashes: Vec<u64> = vec![0; w];
+    let smer_mask = (1u64 << (2 * s)) - 1;
 
-    for i in 0..string.len() - s + 1 {
-        let smer;
-        if s == 8{
-            smer = string.slice(i, i + s).to_owned();
-        }
-        else if s == 10{
-            smer = string.slice(i, i + s).to_owned();
-        }
-        else{
-            panic!("s must be set to 8 or 10 right now");
-        }
-        let rc_smer = smer.rc();
-        let hash_smer;
-        if smer < rc_smer {
-            hash_smer = smer;
+    if string.len() < k + 1 {
+        return (vec![], vec![]);
+    }
+    let is_sync = |offset: usize| {
+        if closed {
+            offset == 0 || offset == w - 1
         } else {
-            hash_smer = rc_smer;
+            offset == t - 1
+        }
+    };
+
+    for i in 0..string.len() - s + 1 {
+        let mut smer = 0u64;
+        let mut rc_smer = 0u64;
+        for j in 0..s {
+            smer = (smer << 2) | string.get(i + j) as u64;
+            rc_smer = (rc_smer << 2) | (3 - string.get(i + s - 1 - j)) as u64;
         }
-        window_hashes[running_pos] = hash(&hash_smer);
+        window_hashes[running_pos] = hash64(u64::min(smer, rc_smer), smer_mask);
         if i < w - 1 {
             continue;
         }
@@ -292,7 +892,7 @@ pub fn open_sync_seeds(
         }
 
         if running_pos > min_running_pos {
-            if running_pos - min_running_pos == t - 1 {
+            if is_sync(running_pos - min_running_pos) {
                 let kmer: Kmer16 = string.slice(i - w + 1, i - w + 1 + k).get_kmer(0);
                 let canonical;
                 let mut node_kmer = kmer.rc();
@@ -325,11 +925,12 @@ pub fn open_sync_seeds(
                         order_val: distance_from_start as u32,
                         color: 1,
                         child_nodes: SmallVec::<[u32; 1]>::new(),
-                        child_edge_distance: SmallVec::<[(u16, (Color, u8)); 1]>::new(),
+                        child_edge_distance: SmallVec::<[(u32, (Color, u8)); 1]>::new(),
                         canonical: canonical, //                    child_nodes: vec![],
                         actual_ref_positions: SmallVec::<[usize; 0]>::new(),
                         repetitive: sample_coord,
                         primary_base: Some(*positions_selected.last().unwrap() as u32),
+                        span: k as u16,
                     };
 
                     if positions_selected.len() % samp_freq == 0 || sample_coord {
@@ -342,7 +943,7 @@ pub fn open_sync_seeds(
                 }
             }
         } else {
-            if w - (min_running_pos - running_pos) == t - 1 {
+            if is_sync((w - (min_running_pos - running_pos)) % w) {
                 let mut distance_from_last = 0;
                 let mut distance_from_start = 0;
                 if !positions_selected.last().is_none() {
@@ -375,11 +976,12 @@ pub fn open_sync_seeds(
                         order_val: distance_from_start as u32,
                         color: 1,
                         child_nodes: SmallVec::<[u32; 1]>::new(),
-                        child_edge_distance: SmallVec::<[(u16, (Color, u8)); 1]>::new(),
+                        child_edge_distance: SmallVec::<[(u32, (Color, u8)); 1]>::new(),
                         canonical: canonical, //                    child_nodes: vec![],
                         actual_ref_positions: SmallVec::<[usize; 0]>::new(),
                         repetitive: false,
                         primary_base: Some(*positions_selected.last().unwrap() as u32),
+                        span: k as u16,
                     };
                     if positions_selected.len() % samp_freq == 0 {
                         kmer_node
@@ -403,19 +1005,216 @@ pub fn open_sync_seeds(
 //            let dist_on_genome = 1;
 //            syncmer_seeds[i]
 //                .child_edge_distance
-//                .push((dist_on_genome as u16, (1, 0)));
+//                .push((dist_on_genome, (1, 0)));
         } else {
             syncmer_seeds[i].child_nodes.push((i + 1) as u32);
             let dist_on_genome = positions_selected[i + 1] - positions_selected[i];
             syncmer_seeds[i]
                 .child_edge_distance
-                .push((dist_on_genome as u16, (1, 0)));
+                .push((dist_on_genome, (1, 0)));
         }
     }
 
     return (syncmer_seeds, positions_selected);
 }
 
+//Order 2 randstrobes made of two k/2-mer strobes. First strobes are the
+//minimizers of the k/2-mers over windows of w, and each is linked to the
+//partner minimising the xor of the two strobe hashes both downstream and
+//upstream in [w_min, w_max], so the same seeds come out of either strand.
+//The seed's k-mer is the two strobes concatenated and its position is placed
+//so that the seed sits in the middle of its span, which keeps the position on
+//the reverse strand at len - pos - k like a k-mer. First strobes are
+//down-weighted like minimizers when the k-mer centred on them is frequent.
+pub fn strobemer_seeds(
+    string: &DnaString,
+    w: usize,
+    k: usize,
+    w_min: usize,
+    w_max: usize,
+    samp_freq: usize,
+    dont_use_kmers: &FxHashSet<Kmer16>,
+    frequent_kmers: &FxHashMap<Kmer16, usize>,
+    is_primary: bool,
+) -> (Vec<KmerNode>, Vec<u32>) {
+    let l = k / 2;
+    assert!(
+        w_min >= l && w_min <= w_max && w_max + l <= u16::MAX as usize,
+        "strobe window must satisfy k/2 <= w_min <= w_max <= u16::MAX - k/2"
+    );
+    if string.len() < usize::max(w, w_min) + l {
+        return (vec![], vec![]);
+    }
+
+    let num_strobes = string.len() - l + 1;
+    let mut strobe_hashes = Vec::with_capacity(num_strobes);
+    let mut weighted_hashes = Vec::with_capacity(num_strobes);
+    for i in 0..num_strobes {
+        let strobe: Kmer8 = string.slice(i, i + l).get_kmer(0);
+        let rc_strobe = strobe.rc();
+        if strobe < rc_strobe {
+            strobe_hashes.push(kmer_hash(&strobe));
+        } else {
+            strobe_hashes.push(kmer_hash(&rc_strobe));
+        }
+        //The k-mer centred on the strobe is the same one on the other strand
+        let mut hash_val = strobe_hashes[i];
+        let start = (i + l / 2).wrapping_sub(k / 2);
+        if i + l / 2 >= k / 2 && start + k <= string.len() {
+            let kmer: Kmer16 = string.slice(start, start + k).get_kmer(0);
+            //Strobe hashes are below 4^l, so frequent strobes go after all
+            //others and keep their order among themselves
+            if let Some(count) = frequent_kmers.get(&kmer.min_rc()) {
+                let num_iters = (*count as f64).log(2.0);
+                hash_val += (num_iters as u64) << (2 * l);
+            }
+        }
+        weighted_hashes.push(hash_val);
+    }
+
+    //Keep the last first strobe while it ties for the window minimum so that
+    //low complexity sequence does not give a strobe at every position
+    let mut first_strobes: Vec<usize> = vec![];
+    for i in 0..num_strobes - w + 1 {
+        let min_pos = i + position_min(&weighted_hashes[i..i + w]).unwrap();
+        if let Some(last) = first_strobes.last() {
+            if *last >= i && weighted_hashes[*last] == weighted_hashes[min_pos] {
+                continue;
+            }
+        }
+        first_strobes.push(min_pos);
+    }
+
+    let best_partner = |i: usize, candidates: &mut dyn Iterator<Item = usize>| {
+        let mut best = None;
+        let mut best_val = (u64::MAX, usize::MAX);
+        for j in candidates {
+            let dist = if j > i { j - i } else { i - j };
+            let val = (strobe_hashes[i] ^ strobe_hashes[j], dist);
+            if val < best_val {
+                best_val = val;
+                best = Some(j);
+            }
+        }
+        best
+    };
+
+    let mut strobemers = vec![];
+    for i in first_strobes {
+        let right_end = usize::min(i + w_max, num_strobes - 1);
+        if i + w_min <= right_end {
+            if let Some(j) = best_partner(i, &mut (i + w_min..right_end + 1)) {
+                strobemers.push((i, j));
+            }
+        }
+        if i >= w_min {
+            let left_end = i.saturating_sub(w_max);
+            if let Some(j) = best_partner(i, &mut (left_end..i - w_min + 1)) {
+                strobemers.push((j, i));
+            }
+        }
+    }
+
+    //On the other strand the seed's k-mer is reverse complemented, so rounding
+    //the middle down for canonical seeds and up otherwise lands on the same base
+    let mut seeds = vec![];
+    for (left, right) in strobemers {
+        let span = right + l - left;
+        let mut bytes = string.slice(left, left + l).bytes();
+        bytes.extend(string.slice(right, right + l).bytes());
+        let kmer = Kmer16::from_bytes(&bytes);
+        let canonical;
+        let node_kmer;
+        if kmer.rc() < kmer {
+            canonical = false;
+            node_kmer = kmer.rc();
+        } else {
+            canonical = true;
+            node_kmer = kmer;
+        }
+        //A palindromic k-mer is canonical on both strands, so the bases it
+        //spans decide the rounding instead
+        let round_down;
+        if kmer == kmer.rc() {
+            let bases = string.slice(left, left + span).to_owned();
+            round_down = bases <= bases.rc();
+        } else {
+            round_down = canonical;
+        }
+        let pos;
+        if round_down {
+            pos = left + (span - k) / 2;
+        } else {
+            pos = left + (span - k + 1) / 2;
+        }
+        seeds.push((pos, node_kmer, canonical, span));
+    }
+    //Chaining needs distinct positions; the smallest k-mer is kept on both strands
+    seeds.sort();
+    seeds.dedup_by_key(|seed| seed.0);
+
+    let mut strobemer_seeds: Vec<KmerNode> = vec![];
+    let mut positions_selected: Vec<u32> = Vec::new();
+    for (pos, node_kmer, canonical, span) in seeds {
+        let mut sample_coord = false;
+        if !positions_selected.last().is_none() {
+            if pos - *positions_selected.last().unwrap() as usize > 500 {
+                sample_coord = true;
+            }
+        }
+        if !dont_use_kmers.contains(&node_kmer) || (sample_coord && is_primary) {
+            positions_selected.push(pos as u32);
+            let mut kmer_node = KmerNode {
+                kmer: node_kmer,
+                id: positions_selected.len() as u32 - 1,
+                order: positions_selected.len() as u32 - 1,
+                order_val: pos as u32,
+                color: 1,
+                child_nodes: SmallVec::<[u32; 1]>::new(),
+                child_edge_distance: SmallVec::<[(u32, (Color, u8)); 1]>::new(),
+                canonical: canonical,
+                actual_ref_positions: SmallVec::<[usize; 0]>::new(),
+                repetitive: sample_coord,
+                primary_base: Some(pos as u32),
+                span: span as u16,
+            };
+            if positions_selected.len() % samp_freq == 0 || sample_coord {
+                kmer_node.actual_ref_positions.push(pos);
+            }
+            if sample_coord {
+                strobemer_seeds.last_mut().unwrap().repetitive = true;
+                if strobemer_seeds.last().unwrap().actual_ref_positions.is_empty() {
+                    strobemer_seeds
+                        .last_mut()
+                        .unwrap()
+                        .actual_ref_positions
+                        .push(positions_selected[positions_selected.len() - 2] as usize);
+                }
+            }
+            strobemer_seeds.push(kmer_node);
+        }
+    }
+
+    for i in 0..strobemer_seeds.len() {
+        if i == strobemer_seeds.len() - 1 {
+            strobemer_seeds[i].child_nodes.push(0 as u32);
+            let dist_on_genome =
+                positions_selected[0] + string.len() as u32 - positions_selected[i];
+            strobemer_seeds[i]
+                .child_edge_distance
+                .push((dist_on_genome, (1, 0)));
+        } else {
+            strobemer_seeds[i].child_nodes.push((i + 1) as u32);
+            let dist_on_genome = positions_selected[i + 1] - positions_selected[i];
+            strobemer_seeds[i]
+                .child_edge_distance
+                .push((dist_on_genome, (1, 0)));
+        }
+    }
+
+    return (strobemer_seeds, positions_selected);
+}
+
 pub fn read_minimizer_count_file(file: &str) -> FxHashMap<Kmer16, usize> {
     let f = File::open(file).expect("Unable to open file");
     let f = BufReader::new(f);
@@ -428,3 +1227,337 @@ pub fn read_minimizer_count_file(file: &str) -> FxHashMap<Kmer16, usize> {
     }
     return return_set;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_dna(len: usize, seed: u64) -> DnaString {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut s = DnaString::new();
+        for _ in 0..len {
+            s.push(rng.gen_range(0..4));
+        }
+        s
+    }
+
+    fn check_seed_kmers(s: &DnaString, w: usize) {
+        let k = 16;
+        let (seeds, positions) = minimizer_seeds(
+            s,
+            w,
+            k,
+            1,
+            &FxHashSet::default(),
+            &FxHashMap::default(),
+            true,
+        );
+        assert_eq!(seeds.len(), positions.len());
+        for (seed, &pos) in seeds.iter().zip(positions.iter()) {
+            let kmer: Kmer16 = s.slice(pos as usize, pos as usize + k).get_kmer(0);
+            if seed.canonical {
+                assert_eq!(seed.kmer, kmer);
+            } else {
+                assert_eq!(seed.kmer, kmer.rc());
+            }
+            assert!(seed.kmer <= seed.kmer.rc());
+        }
+        for i in 1..positions.len() {
+            assert!(positions[i - 1] < positions[i]);
+        }
+    }
+
+    #[test]
+    fn hash64_is_fixed() {
+        let mask = (1u64 << 32) - 1;
+        assert_eq!(hash64(0, mask), 4290886808);
+        assert_eq!(hash64(1, mask), 3079993582);
+        assert_eq!(hash64(0x12345678, mask), 391856246);
+        assert_eq!(hash64(mask, mask), 3365156203);
+    }
+
+    #[test]
+    fn hash64_is_invertible() {
+        let mask = (1u64 << 16) - 1;
+        let mut seen = vec![false; 1 << 16];
+        for key in 0..=mask {
+            let h = hash64(key, mask) as usize;
+            assert!(!seen[h]);
+            seen[h] = true;
+        }
+    }
+
+    #[test]
+    fn rolled_kmers_match_slices() {
+        let s = random_dna(20000, 35);
+        for &w in [1, 2, 5, 10, 16, 31].iter() {
+            check_seed_kmers(&s, w);
+        }
+    }
+
+    #[test]
+    fn rolled_kmers_low_complexity() {
+        let mut s = DnaString::new();
+        for i in 0..5000 {
+            if i % 1000 < 500 {
+                s.push(0);
+            } else {
+                s.push((i % 3) as u8);
+            }
+        }
+        for &w in [1, 10, 16].iter() {
+            check_seed_kmers(&s, w);
+        }
+    }
+
+    //Random background with each k-mer inserted copies[i] times, every copy
+    //followed by random spacer
+    fn genome_with_repeats(
+        kmers: &Vec<Vec<u8>>,
+        copies: &[usize],
+        rng: &mut impl rand::Rng,
+    ) -> DnaString {
+        let mut seq = vec![];
+        for _ in 0..2000 {
+            seq.push(b"ACGT"[rng.gen_range(0..4)]);
+        }
+        for (kmer, num) in kmers.iter().zip(copies.iter()) {
+            for _ in 0..*num {
+                seq.extend(kmer);
+                for _ in 0..50 {
+                    seq.push(b"ACGT"[rng.gen_range(0..4)]);
+                }
+            }
+        }
+        return DnaString::from_acgt_bytes(&seq);
+    }
+
+    #[test]
+    fn frequent_kmers_threshold() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(32);
+        let min = constants::FREQUENT_KMER_MIN_COUNT;
+        let mut kmers = vec![];
+        for _ in 0..4 {
+            kmers.push((0..16).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect::<Vec<u8>>());
+        }
+        //Copy numbers average over the genomes that have the k-mer, rounding
+        //half up: min and min, min - 1 and min - 1, 2 * min - 1 and none,
+        //min and min - 2
+        let genomes = vec![
+            (genome_with_repeats(&kmers, &[min, min - 1, 2 * min - 1, min], &mut rng), true),
+            (genome_with_repeats(&kmers, &[min, min - 1, 0, min - 2], &mut rng), true),
+        ];
+        let frequent_kmers = get_frequent_kmers(&genomes, &crate::test_utils::minimizer_params());
+        let canonical: Vec<Kmer16> = kmers
+            .iter()
+            .map(|kmer| Kmer16::from_ascii(kmer).min_rc())
+            .collect();
+        assert_eq!(frequent_kmers.get(&canonical[0]), Some(&min));
+        assert_eq!(frequent_kmers.get(&canonical[1]), None);
+        assert_eq!(frequent_kmers.get(&canonical[2]), Some(&(2 * min - 1)));
+        assert_eq!(frequent_kmers.get(&canonical[3]), None);
+        assert_eq!(frequent_kmers.len(), 2);
+    }
+
+    #[test]
+    fn compressed_seeds_map_back_to_kmers() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(33);
+        //Random runs, with one run too long for a seed's span
+        let mut seq = vec![];
+        for i in 0..3000 {
+            let run_len;
+            if i == 1500 {
+                run_len = 70000;
+            } else {
+                run_len = rng.gen_range(1..5);
+            }
+            let base = b"ACGT"[rng.gen_range(0..4)];
+            for _ in 0..run_len {
+                seq.push(base);
+            }
+        }
+        let string = DnaString::from_acgt_bytes(&seq);
+        let mut seed_params = crate::test_utils::minimizer_params();
+        seed_params.hpc = true;
+        let (seeds, positions) = seed_sequence(
+            &string,
+            &seed_params,
+            1,
+            &FxHashSet::default(),
+            &FxHashMap::default(),
+            &vec![],
+            false,
+        );
+        assert!(seeds.len() > 100);
+        let k = seed_params.k;
+        for (i, seed) in seeds.iter().enumerate() {
+            if i > 0 {
+                assert!(positions[i] > positions[i - 1]);
+            }
+            //The bases covered, found back from the middle as
+            //for masking, compress to the seed's k-mer
+            let extra = seed.span as usize - k;
+            let start;
+            if seed.canonical {
+                start = positions[i] as usize - extra / 2;
+            } else {
+                start = positions[i] as usize - (extra + 1) / 2;
+            }
+            let end = start + seed.span as usize;
+            assert!(start == 0 || seq[start - 1] != seq[start]);
+            assert!(end == seq.len() || seq[end] != seq[end - 1]);
+            let (compressed, _) = homopolymer_compress(&string.slice(start, end).to_owned());
+            assert_eq!(compressed.len(), k);
+            let kmer: Kmer16 = compressed.get_kmer(0);
+            if seed.canonical {
+                assert_eq!(seed.kmer, kmer);
+            } else {
+                assert_eq!(seed.kmer, kmer.rc());
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_stop_at_n_runs() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(34);
+        let mut seq: Vec<u8> = (0..20000).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+        //Longer than an edge distance used to hold
+        let gap = (10000, 80000);
+        seq.splice(gap.0..gap.0, vec![b'N'; gap.1 - gap.0]);
+        let seed_params = crate::test_utils::minimizer_params();
+        let seed = |bytes: &[u8], masked: &Vec<(usize, usize)>| {
+            seed_sequence(
+                &DnaString::from_acgt_bytes(bytes),
+                &seed_params,
+                1,
+                &FxHashSet::default(),
+                &FxHashMap::default(),
+                masked,
+                false,
+            )
+        };
+        let masked = get_masked_intervals(&seq, false);
+        assert_eq!(masked, vec![gap]);
+        let (seeds, positions) = seed(&seq, &masked);
+        let (left_seeds, left_positions) = seed(&seq[..gap.0], &vec![]);
+        let (right_seeds, right_positions) = seed(&seq[gap.1..], &vec![]);
+
+        assert_eq!(seeds.len(), left_seeds.len() + right_seeds.len());
+        for (i, pos) in positions.iter().enumerate() {
+            let pos = *pos as usize;
+            assert!(pos + seeds[i].span as usize <= gap.0 || pos >= gap.1);
+            let (split_kmer, split_pos);
+            if i < left_seeds.len() {
+                split_kmer = left_seeds[i].kmer;
+                split_pos = left_positions[i] as usize;
+            } else {
+                split_kmer = right_seeds[i - left_seeds.len()].kmer;
+                split_pos = right_positions[i - left_seeds.len()] as usize + gap.1;
+            }
+            assert_eq!(seeds[i].kmer, split_kmer);
+            assert_eq!(pos, split_pos);
+            if i + 1 < seeds.len() {
+                assert_eq!(seeds[i].child_nodes[0], i as u32 + 1);
+                assert_eq!(
+                    seeds[i].child_edge_distance[0].0,
+                    positions[i + 1] - positions[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn deque_minimizers_match_ring_buffer() {
+        let genomes = crate::test_utils::test_genomes(&crate::test_utils::TEST_REFS, 300000);
+        let mut chroms = vec![];
+        for genome in genomes.iter() {
+            chroms.push((DnaString::from_acgt_bytes(genome), true));
+        }
+        let seed_params = crate::test_utils::minimizer_params();
+        let frequent_kmers = get_frequent_kmers(&chroms, &seed_params);
+        let dont_use_kmers = get_masked_kmers(
+            &chroms,
+            &vec![vec![]; chroms.len()],
+            &seed_params,
+            0.01,
+            &frequent_kmers,
+        );
+        //Runs of a single base give long stretches of tied hashes
+        let mut low_complexity = vec![b'A'; 2000];
+        low_complexity.extend(b"ACGTTGCA".repeat(300));
+        low_complexity.extend(vec![b'C'; 2000]);
+        chroms.push((DnaString::from_acgt_bytes(&low_complexity), true));
+        chroms.push((random_dna(3000, 350), true));
+        for (genome, _) in chroms.iter() {
+            for &w in [1, 2, 5, 10, 16, 31, 50].iter() {
+                for &samp_freq in [1, 30].iter() {
+                    let seeds = minimizer_seeds(
+                        genome,
+                        w,
+                        16,
+                        samp_freq,
+                        &dont_use_kmers,
+                        &frequent_kmers,
+                        true,
+                    );
+                    let reference = sliced_minimizer_seeds(
+                        genome,
+                        w,
+                        16,
+                        samp_freq,
+                        &dont_use_kmers,
+                        &frequent_kmers,
+                        true,
+                    );
+                    assert_eq!(seeds.1, reference.1);
+                    assert_eq!(format!("{:?}", seeds.0), format!("{:?}", reference.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strobemers_weight_frequent_kmers() {
+        let s = random_dna(5000, 36);
+        let rc = s.rc();
+        let strobes = |string: &DnaString, frequent_kmers: &FxHashMap<Kmer16, usize>| {
+            strobemer_seeds(string, 10, 16, 8, 40, 1, &FxHashSet::default(), frequent_kmers, true)
+        };
+        let mut frequent_kmers = FxHashMap::default();
+        for i in (0..s.len() - 16).step_by(3) {
+            let kmer: Kmer16 = s.slice(i, i + 16).get_kmer(0);
+            frequent_kmers.insert(kmer.min_rc(), 1024);
+        }
+        let (unweighted, _) = strobes(&s, &FxHashMap::default());
+        let (seeds, positions) = strobes(&s, &frequent_kmers);
+        assert_ne!(
+            unweighted.iter().map(|x| x.kmer).collect::<Vec<Kmer16>>(),
+            seeds.iter().map(|x| x.kmer).collect::<Vec<Kmer16>>()
+        );
+
+        //The weighting doesn't break strand symmetry away from the ends, where
+        //the strobe windows are cut short
+        let (rc_seeds, rc_positions) = strobes(&rc, &frequent_kmers);
+        let interior = |pos: u32| pos >= 100 && pos < s.len() as u32 - 100;
+        let mut forward = vec![];
+        for (seed, pos) in seeds.iter().zip(positions.iter()) {
+            let rc_pos = s.len() as u32 - pos - 16;
+            if interior(rc_pos) {
+                forward.push((rc_pos, seed.kmer));
+            }
+        }
+        forward.reverse();
+        let mut reverse = vec![];
+        for (seed, pos) in rc_seeds.iter().zip(rc_positions.iter()) {
+            if interior(*pos) {
+                reverse.push((*pos, seed.kmer));
+            }
+        }
+        assert!(forward.len() > 500);
+        assert_eq!(forward, reverse);
+    }
+}
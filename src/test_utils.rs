@@ -0,0 +1,150 @@
+//Graphs from the test_refs genomes for unit tests, built the way generate
+//builds them.
+use crate::chain;
+use crate::constants;
+use crate::data_structs::{KmerNode, SeedMethod, SeedParams, SeqGraph};
+use crate::graph_utils;
+use crate::seeding_methods_bit;
+use bio::io::fasta;
+use debruijn::dna_string::DnaString;
+use debruijn::kmer::Kmer16;
+use fxhash::{FxHashMap, FxHashSet};
+use rand::{Rng, SeedableRng};
+
+pub const TEST_REFS: [&str; 3] = [
+    "test_refs/ash1.7_chr20-1M.fa",
+    "test_refs/chm13_chr20-1M.fa",
+    "test_refs/grch38_chr20-1M.fa",
+];
+
+//First len bases of the first record of each file
+pub fn test_genomes(files: &[&str], len: usize) -> Vec<Vec<u8>> {
+    let mut genomes = vec![];
+    for file in files.iter() {
+        let reader = fasta::Reader::from_file(file).unwrap();
+        let rec = reader.records().next().unwrap().unwrap();
+        let seq = rec.seq();
+        genomes.push(seq[..usize::min(len, seq.len())].to_vec());
+    }
+    return genomes;
+}
+
+pub fn minimizer_params() -> SeedParams {
+    SeedParams {
+        method: SeedMethod::Minimizer,
+        k: 16,
+        w: 16,
+        s: 8,
+        t: 5,
+        strobe_w_min: 8,
+        strobe_w_max: 40,
+        hpc: false,
+        soft_mask: false,
+        seed_hash: constants::SEED_HASH.to_string(),
+    }
+}
+
+pub struct TestGraph {
+    pub nodes: Vec<KmerNode>,
+    pub chroms: Vec<(DnaString, bool)>,
+    pub seq_graph: SeqGraph,
+    pub dont_use_kmers: FxHashSet<Kmer16>,
+    pub frequent_kmers: FxHashMap<Kmer16, usize>,
+}
+
+//Graph of the genomes, each aligned to the graph so far as in generate. The
+//strand of each genome relative to the graph is found by chaining.
+pub fn build_graph(genomes: &Vec<Vec<u8>>, seed_params: &SeedParams) -> TestGraph {
+    let samp_freq = 30;
+    let k = seed_params.k;
+    let params = constants::default_params();
+    let mut chroms = vec![];
+    let mut gaps = vec![];
+    for genome in genomes.iter() {
+        chroms.push((DnaString::from_acgt_bytes(genome), true));
+        gaps.push(seeding_methods_bit::get_masked_intervals(genome, false));
+    }
+    let frequent_kmers = seeding_methods_bit::get_frequent_kmers(&chroms, seed_params);
+    let dont_use_kmers =
+        seeding_methods_bit::get_masked_kmers(&chroms, &gaps, seed_params, 0.0002, &frequent_kmers);
+    let (mut nodes, first_positions) = seeding_methods_bit::seed_sequence(
+        &chroms[0].0,
+        seed_params,
+        samp_freq,
+        &dont_use_kmers,
+        &frequent_kmers,
+        &gaps[0],
+        true,
+    );
+    graph_utils::top_sort(&mut nodes);
+    let mut genome_paths = vec![first_positions
+        .iter()
+        .enumerate()
+        .map(|(i, pos)| (i as u32, *pos as usize))
+        .collect::<Vec<(u32, usize)>>()];
+    for i in 1..chroms.len() {
+        let (seeds, _) = seeding_methods_bit::seed_sequence(
+            &chroms[i].0,
+            seed_params,
+            1,
+            &dont_use_kmers,
+            &frequent_kmers,
+            &gaps[i],
+            false,
+        );
+        let ref_hash_map = chain::get_kmer_dict_mut(&mut nodes);
+        let q_hash_map = chain::get_kmer_dict(&seeds);
+        let (anchors, _score, forward_strand) = chain::chain_seeds(
+            &nodes,
+            &seeds,
+            &ref_hash_map,
+            &q_hash_map,
+            &params,
+            true,
+            false,
+            &dont_use_kmers,
+            false,
+            seed_params.method,
+        )
+        .into_iter()
+        .max_by(|x, y| x.1.total_cmp(&y.1))
+        .unwrap();
+        chroms[i].1 = forward_strand;
+        genome_paths.push(graph_utils::add_align_to_graph(
+            &mut nodes,
+            seeds,
+            anchors,
+            forward_strand,
+            samp_freq,
+            false,
+        ));
+        graph_utils::top_sort(&mut nodes);
+    }
+    let seq_graph = graph_utils::label_edges(&nodes, &genome_paths, &chroms, &gaps, k);
+    return TestGraph {
+        nodes: nodes,
+        chroms: chroms,
+        seq_graph: seq_graph,
+        dont_use_kmers: dont_use_kmers,
+        frequent_kmers: frequent_kmers,
+    };
+}
+
+//Copy of seq with substitutions, deletions and insertions each at rate / 3
+pub fn mutate(seq: &[u8], rate: f64, seed: u64) -> Vec<u8> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut mutated = vec![];
+    for c in seq.iter() {
+        let x: f64 = rng.gen();
+        if x < rate / 3. {
+            mutated.push(b"ACGT"[rng.gen_range(0..4)]);
+        } else if x < 2. * rate / 3. {
+        } else if x < rate {
+            mutated.push(*c);
+            mutated.push(b"ACGT"[rng.gen_range(0..4)]);
+        } else {
+            mutated.push(*c);
+        }
+    }
+    return mutated;
+}